@@ -0,0 +1,58 @@
+//! Compares the per-pixel `draw_iter` path with the byte-wise fill overrides
+//! on a full screen fill. Run with `cargo bench`.
+use std::{
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use embedded_graphics::{prelude::*, primitives::Rectangle};
+use inky_frame_rs::display::{color::OctColor, DisplayRotation, InkyFrameDisplay};
+
+const ITERATIONS: u32 = 20;
+
+fn time<F: FnMut(&mut InkyFrameDisplay)>(display: &mut InkyFrameDisplay, mut f: F) -> Duration {
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f(black_box(&mut *display));
+    }
+    start.elapsed() / ITERATIONS
+}
+
+fn main() {
+    let rotations = [
+        ("Rotate0", DisplayRotation::Rotate0),
+        ("Rotate90", DisplayRotation::Rotate90),
+        ("Rotate180", DisplayRotation::Rotate180),
+        ("Rotate270", DisplayRotation::Rotate270),
+    ];
+
+    for (name, rotation) in rotations {
+        let mut display = InkyFrameDisplay::default();
+        display.set_rotation(rotation);
+        let area = display.bounding_box();
+
+        let per_pixel = time(&mut display, |d| {
+            d.draw_iter(area.points().map(|p| Pixel(p, OctColor::Red)))
+                .unwrap();
+        });
+        let solid = time(&mut display, |d| {
+            d.fill_solid(&area, OctColor::Red).unwrap()
+        });
+        let contiguous = time(&mut display, |d| {
+            d.fill_contiguous(&area, core::iter::repeat(OctColor::Blue))
+                .unwrap();
+        });
+        let clear = time(&mut display, |d| d.clear(OctColor::Green).unwrap());
+        let inset = Rectangle::new(Point::new(1, 1), area.size - Size::new(2, 2));
+        let unaligned = time(&mut display, |d| {
+            d.fill_solid(&inset, OctColor::Red).unwrap()
+        });
+
+        println!("{name}:");
+        println!("  draw_iter           {per_pixel:?}");
+        println!("  fill_solid          {solid:?}");
+        println!("  fill_solid (inset)  {unaligned:?}");
+        println!("  fill_contiguous     {contiguous:?}");
+        println!("  clear               {clear:?}");
+    }
+}
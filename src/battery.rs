@@ -0,0 +1,466 @@
+//! Battery voltage and state of charge, read from the VSYS divider
+//!
+//! The refresh draws a lot of current, and starting one on a flat battery can
+//! brown the board out half way through. [`Battery::guard`] gives a
+//! [`RefreshHook`] that aborts the display's `_with` methods, e.g.
+//! [`display_frame_with`](crate::display::InkyFrame5_7::display_frame_with),
+//! before the panel is powered on when the voltage is too low.
+//! [`Battery::guarded_display_frame`] does that for a plain refresh. The
+//! driver doesn't know about the battery, so
+//! [`display_frame`](crate::display::InkyFrame5_7::display_frame) and the
+//! other methods without a hook refresh whatever the battery says.
+//!
+//! ```ignore
+//! let mut battery = Battery::new(vsys_pin, Chemistry::LiPo);
+//! match battery.guarded_display_frame(&mut adc, &mut display, &mut spi, &mut shift_register) {
+//!     Err(RefreshError::Aborted(RefreshPhase::Pending)) => { /* battery too low */ }
+//!     _ => {}
+//! }
+//! ```
+use embedded_hal::adc::{Channel, OneShot};
+use embedded_hal::{blocking::spi::Write, digital::v2::OutputPin};
+
+use crate::display::{
+    InkyFrame5_7, IsBusy, NoRefreshHook, Progress, RefreshError, RefreshHook, RefreshPhase,
+};
+
+/// Readings averaged for every measurement
+const SAMPLES: u32 = 8;
+
+/// What the frame is powered from, picks the discharge curve
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Chemistry {
+    /// A single cell lithium polymer or lithium ion battery
+    LiPo,
+    /// Three alkaline AA cells in series
+    Alkaline3xAA,
+    /// Three rechargeable NiMH AA cells in series
+    NiMh3xAA,
+}
+
+impl Chemistry {
+    /// Voltage in millivolts and the charge left at it, by rising voltage
+    const fn curve(self) -> &'static [(u16, u8)] {
+        match self {
+            Chemistry::LiPo => &[
+                (3300, 0),
+                (3500, 5),
+                (3600, 10),
+                (3700, 30),
+                (3750, 45),
+                (3800, 55),
+                (3900, 70),
+                (4000, 82),
+                (4100, 92),
+                (4200, 100),
+            ],
+            Chemistry::Alkaline3xAA => &[
+                (3000, 0),
+                (3300, 10),
+                (3450, 25),
+                (3600, 45),
+                (3750, 65),
+                (3900, 80),
+                (4050, 92),
+                (4500, 100),
+            ],
+            Chemistry::NiMh3xAA => &[
+                (3000, 0),
+                (3300, 5),
+                (3450, 15),
+                (3600, 40),
+                (3750, 70),
+                (3900, 90),
+                (4050, 100),
+            ],
+        }
+    }
+
+    /// Lowest voltage in millivolts a refresh is started at by default
+    pub const fn min_refresh_millivolts(self) -> u16 {
+        match self {
+            Chemistry::LiPo => 3500,
+            Chemistry::Alkaline3xAA | Chemistry::NiMh3xAA => 3300,
+        }
+    }
+
+    /// Charge left in percent at the voltage, interpolated along the
+    /// chemistry's discharge curve
+    pub const fn state_of_charge(self, millivolts: u16) -> u8 {
+        let curve = self.curve();
+        if millivolts <= curve[0].0 {
+            return curve[0].1;
+        }
+        let mut i = 1;
+        while i < curve.len() {
+            let (high_mv, high_percent) = curve[i];
+            if millivolts < high_mv {
+                let (low_mv, low_percent) = curve[i - 1];
+                let span = (high_percent - low_percent) as u32;
+                let offset = (millivolts - low_mv) as u32 * span / (high_mv - low_mv) as u32;
+                return low_percent + offset as u8;
+            }
+            i += 1;
+        }
+        curve[curve.len() - 1].1
+    }
+}
+
+/// The VSYS voltage sense input
+///
+/// Defaults match the Pico: VSYS divided by 3 into a 12 bit ADC with a
+/// 3.3 V reference. On a Pico W the ADC pin is shared with the wireless chip,
+/// which has to be idle while it is read.
+pub struct Battery<Pin> {
+    pin: Pin,
+    chemistry: Chemistry,
+    divider_numerator: u32,
+    divider_denominator: u32,
+    reference_millivolts: u32,
+    full_scale: u32,
+    min_refresh_millivolts: u16,
+}
+
+impl<Pin> Battery<Pin> {
+    pub fn new(pin: Pin, chemistry: Chemistry) -> Self {
+        Battery {
+            pin,
+            chemistry,
+            divider_numerator: 3,
+            divider_denominator: 1,
+            reference_millivolts: 3300,
+            full_scale: 4095,
+            min_refresh_millivolts: chemistry.min_refresh_millivolts(),
+        }
+    }
+
+    /// Sets the ratio of VSYS to the voltage at the pin
+    pub fn with_divider(mut self, numerator: u32, denominator: u32) -> Self {
+        self.divider_numerator = numerator;
+        self.divider_denominator = denominator.max(1);
+        self
+    }
+
+    /// Sets the ADC's reference voltage and its highest reading
+    pub fn with_reference(mut self, reference_millivolts: u32, full_scale: u32) -> Self {
+        self.reference_millivolts = reference_millivolts;
+        self.full_scale = full_scale.max(1);
+        self
+    }
+
+    /// Sets the lowest voltage a refresh is started at, see [`guard`](Self::guard)
+    pub fn with_min_refresh_millivolts(mut self, millivolts: u16) -> Self {
+        self.min_refresh_millivolts = millivolts;
+        self
+    }
+
+    pub fn set_chemistry(&mut self, chemistry: Chemistry) {
+        self.chemistry = chemistry;
+    }
+
+    pub fn chemistry(&self) -> Chemistry {
+        self.chemistry
+    }
+
+    /// VSYS in millivolts for a raw ADC reading
+    pub fn to_millivolts(&self, raw: u16) -> u16 {
+        let millivolts =
+            raw as u64 * self.reference_millivolts as u64 * self.divider_numerator as u64
+                / (self.full_scale as u64 * self.divider_denominator as u64);
+        millivolts.min(u16::MAX as u64) as u16
+    }
+
+    /// Gives the pin back
+    pub fn release(self) -> Pin {
+        self.pin
+    }
+
+    /// Measures VSYS in millivolts, averaging a few readings
+    pub fn millivolts<ADC, A>(&mut self, adc: &mut A) -> Result<u16, A::Error>
+    where
+        Pin: Channel<ADC>,
+        A: OneShot<ADC, u16, Pin>,
+    {
+        let mut total = 0u32;
+        for _ in 0..SAMPLES {
+            total += nb::block!(adc.read(&mut self.pin))? as u32;
+        }
+        Ok(self.to_millivolts((total / SAMPLES) as u16))
+    }
+
+    /// Measures the charge left in percent
+    pub fn state_of_charge<ADC, A>(&mut self, adc: &mut A) -> Result<u8, A::Error>
+    where
+        Pin: Channel<ADC>,
+        A: OneShot<ADC, u16, Pin>,
+    {
+        Ok(self.chemistry.state_of_charge(self.millivolts(adc)?))
+    }
+
+    /// Whether there is enough voltage to start a refresh
+    pub fn can_refresh<ADC, A>(&mut self, adc: &mut A) -> Result<bool, A::Error>
+    where
+        Pin: Channel<ADC>,
+        A: OneShot<ADC, u16, Pin>,
+    {
+        Ok(self.millivolts(adc)? >= self.min_refresh_millivolts)
+    }
+
+    /// Refresh hook that aborts before the panel is powered on when the
+    /// battery is too low, or can't be read
+    pub fn guard<'a, ADC, A>(&'a mut self, adc: &'a mut A) -> BatteryGuard<'a, Pin, A, ADC>
+    where
+        Pin: Channel<ADC>,
+        A: OneShot<ADC, u16, Pin>,
+    {
+        BatteryGuard {
+            battery: self,
+            adc,
+            hook: NoRefreshHook,
+            checked: false,
+            _adc: core::marker::PhantomData,
+        }
+    }
+
+    /// Shows the frame sent to the display unless the battery is too low,
+    /// which gives `RefreshError::Aborted(RefreshPhase::Pending)`
+    ///
+    /// The same as [`display_frame_with`](InkyFrame5_7::display_frame_with)
+    /// and [`guard`](Self::guard).
+    pub fn guarded_display_frame<ADC, A, SPI, CS, DC, RST>(
+        &mut self,
+        adc: &mut A,
+        display: &mut InkyFrame5_7<SPI, CS, DC, RST>,
+        spi: &mut SPI,
+        busy_signal: &mut impl IsBusy,
+    ) -> Result<(), RefreshError<SPI::Error>>
+    where
+        Pin: Channel<ADC>,
+        A: OneShot<ADC, u16, Pin>,
+        SPI: Write<u8>,
+        CS: OutputPin,
+        DC: OutputPin,
+        RST: OutputPin,
+    {
+        display.display_frame_with(spi, busy_signal, &mut self.guard(adc))
+    }
+}
+
+/// [`RefreshHook`] that checks the battery once before a refresh, made by
+/// [`Battery::guard`]
+///
+/// Every other call is passed on to the wrapped hook.
+pub struct BatteryGuard<'a, Pin, A, ADC, H = NoRefreshHook> {
+    battery: &'a mut Battery<Pin>,
+    adc: &'a mut A,
+    hook: H,
+    checked: bool,
+    _adc: core::marker::PhantomData<ADC>,
+}
+
+impl<'a, Pin, A, ADC, H> BatteryGuard<'a, Pin, A, ADC, H> {
+    /// Passes the calls after the battery check on to `hook`
+    pub fn with_hook<H2: RefreshHook>(self, hook: H2) -> BatteryGuard<'a, Pin, A, ADC, H2> {
+        BatteryGuard {
+            battery: self.battery,
+            adc: self.adc,
+            hook,
+            checked: self.checked,
+            _adc: core::marker::PhantomData,
+        }
+    }
+}
+
+impl<Pin, A, ADC, H> RefreshHook for BatteryGuard<'_, Pin, A, ADC, H>
+where
+    Pin: Channel<ADC>,
+    A: OneShot<ADC, u16, Pin>,
+    H: RefreshHook,
+{
+    fn now(&mut self) -> u32 {
+        self.hook.now()
+    }
+
+    fn progress(&mut self, phase: RefreshPhase, elapsed: u32) -> Progress {
+        if !self.checked {
+            self.checked = true;
+            if !matches!(self.battery.can_refresh(self.adc), Ok(true)) {
+                return Progress::Abort;
+            }
+        }
+        self.hook.progress(phase, elapsed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::Cell;
+    use core::convert::Infallible;
+
+    /// ADC with a fixed reading, `None` fails
+    struct Adc(Cell<Option<u16>>);
+
+    struct Vsys;
+
+    impl Channel<Adc> for Vsys {
+        type ID = u8;
+
+        fn channel() -> u8 {
+            3
+        }
+    }
+
+    impl OneShot<Adc, u16, Vsys> for Adc {
+        type Error = ();
+
+        fn read(&mut self, _pin: &mut Vsys) -> nb::Result<u16, ()> {
+            self.0.get().ok_or(nb::Error::Other(()))
+        }
+    }
+
+    /// Display pins and bus that do nothing, the panel is never busy
+    struct Idle;
+
+    impl OutputPin for Idle {
+        type Error = Infallible;
+
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            Ok(())
+        }
+    }
+
+    impl Write<u8> for Idle {
+        type Error = Infallible;
+
+        fn write(&mut self, _words: &[u8]) -> Result<(), Infallible> {
+            Ok(())
+        }
+    }
+
+    impl IsBusy for Idle {
+        fn is_busy(&mut self) -> bool {
+            false
+        }
+    }
+
+    /// Counts the calls that get passed on
+    struct Counter(u32);
+
+    impl RefreshHook for Counter {
+        fn now(&mut self) -> u32 {
+            0
+        }
+
+        fn progress(&mut self, _phase: RefreshPhase, _elapsed: u32) -> Progress {
+            self.0 += 1;
+            Progress::Continue
+        }
+    }
+
+    // 2417mV and 4199mV with the default divider
+    const LOW: u16 = 1000;
+    const FULL: u16 = 1737;
+
+    const CHEMISTRIES: [Chemistry; 3] = [
+        Chemistry::LiPo,
+        Chemistry::Alkaline3xAA,
+        Chemistry::NiMh3xAA,
+    ];
+
+    #[test]
+    fn state_of_charge_at_the_knots() {
+        for chemistry in CHEMISTRIES {
+            for &(millivolts, percent) in chemistry.curve() {
+                assert_eq!(
+                    chemistry.state_of_charge(millivolts),
+                    percent,
+                    "{chemistry:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn state_of_charge_past_the_ends() {
+        for chemistry in CHEMISTRIES {
+            assert_eq!(chemistry.state_of_charge(0), 0);
+            assert_eq!(chemistry.state_of_charge(2999), 0);
+            assert_eq!(chemistry.state_of_charge(4500), 100);
+            assert_eq!(chemistry.state_of_charge(u16::MAX), 100);
+        }
+    }
+
+    #[test]
+    fn state_of_charge_between_the_knots() {
+        assert_eq!(Chemistry::LiPo.state_of_charge(3650), 20);
+        assert_eq!(Chemistry::LiPo.state_of_charge(4199), 99);
+        assert_eq!(Chemistry::Alkaline3xAA.state_of_charge(3150), 5);
+        assert_eq!(Chemistry::NiMh3xAA.state_of_charge(3675), 55);
+    }
+
+    #[test]
+    fn millivolts_with_the_default_divider() {
+        let battery = Battery::new((), Chemistry::LiPo);
+        assert_eq!(battery.to_millivolts(0), 0);
+        // 3.3 V at the pin is 9.9 V on VSYS
+        assert_eq!(battery.to_millivolts(4095), 9900);
+        assert_eq!(battery.to_millivolts(2048), 4951);
+        // a full LiPo reads 1.4 V at the pin
+        assert_eq!(battery.to_millivolts(1737), 4199);
+    }
+
+    #[test]
+    fn guard_aborts_on_a_low_battery() {
+        let mut battery = Battery::new(Vsys, Chemistry::LiPo);
+        let mut adc = Adc(Cell::new(Some(LOW)));
+        let mut guard = battery.guard(&mut adc);
+        assert_eq!(guard.progress(RefreshPhase::Pending, 0), Progress::Abort);
+
+        let mut adc = Adc(Cell::new(None));
+        let mut guard = battery.guard(&mut adc);
+        assert_eq!(guard.progress(RefreshPhase::Pending, 0), Progress::Abort);
+    }
+
+    #[test]
+    fn guard_checks_once_then_passes_calls_on() {
+        let mut battery = Battery::new(Vsys, Chemistry::LiPo);
+        let mut adc = Adc(Cell::new(Some(FULL)));
+        let mut guard = battery.guard(&mut adc).with_hook(Counter(0));
+        assert_eq!(guard.progress(RefreshPhase::Pending, 0), Progress::Continue);
+        guard.adc.0.set(Some(LOW));
+        assert_eq!(
+            guard.progress(RefreshPhase::Refresh, 10),
+            Progress::Continue
+        );
+        assert_eq!(guard.hook.0, 2);
+    }
+
+    #[test]
+    fn guarded_refresh_is_aborted_before_power_on() {
+        let mut spi = Idle;
+        let mut display = InkyFrame5_7::new(&mut spi, Idle, Idle, Idle, &mut Idle).unwrap();
+        let mut battery = Battery::new(Vsys, Chemistry::LiPo);
+
+        let mut adc = Adc(Cell::new(Some(LOW)));
+        assert_eq!(
+            battery.guarded_display_frame(&mut adc, &mut display, &mut spi, &mut Idle),
+            Err(RefreshError::Aborted(RefreshPhase::Pending))
+        );
+        assert_eq!(
+            display.display_frame_with(&mut spi, &mut Idle, &mut battery.guard(&mut adc)),
+            Err(RefreshError::Aborted(RefreshPhase::Pending))
+        );
+
+        adc.0.set(Some(FULL));
+        assert_eq!(
+            battery.guarded_display_frame(&mut adc, &mut display, &mut spi, &mut Idle),
+            Ok(())
+        );
+    }
+}
@@ -0,0 +1,414 @@
+//! Turns shift register snapshots into button events
+//!
+//! Feed [`ButtonEvents::update`] with [`Inputs`] read every few milliseconds,
+//! along with a millisecond timestamp, then take the events out with
+//! [`ButtonEvents::pop`]. Timestamps are allowed to wrap around.
+use crate::shift_register::{Button, Inputs};
+
+/// Something that happened to the buttons
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ButtonEvent {
+    /// The button went down
+    Pressed(Button),
+    /// The button went up again
+    Released(Button),
+    /// The button has been held down for the long press time
+    LongPress(Button),
+    /// The button is still held down after a long press, sent every repeat interval
+    Repeat(Button),
+    /// Two or more buttons are held down together, sent when the combination changes
+    Chord(ButtonSet),
+}
+
+/// A set of buttons
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct ButtonSet(u8);
+
+impl ButtonSet {
+    /// Whether the button is in the set
+    pub const fn contains(self, button: Button) -> bool {
+        self.0 & (1 << button.bit()) != 0
+    }
+
+    /// How many buttons are in the set
+    pub const fn len(self) -> u32 {
+        self.0.count_ones()
+    }
+
+    /// Whether the set has no buttons
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// The buttons in the set
+    pub fn iter(self) -> impl Iterator<Item = Button> {
+        Button::ALL
+            .into_iter()
+            .filter(move |button| self.contains(*button))
+    }
+
+    const fn with(self, button: Button) -> ButtonSet {
+        ButtonSet(self.0 | 1 << button.bit())
+    }
+}
+
+/// Timings of the button state machine, all in milliseconds
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ButtonTimings {
+    /// How long a level has to be stable before it counts
+    pub debounce: u32,
+    /// How long a button has to be held down for a [`ButtonEvent::LongPress`]
+    pub long_press: u32,
+    /// Time between [`ButtonEvent::Repeat`]s after a long press, 0 turns them off
+    pub repeat_interval: u32,
+}
+
+impl Default for ButtonTimings {
+    fn default() -> Self {
+        ButtonTimings {
+            debounce: 20,
+            long_press: 800,
+            repeat_interval: 200,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct ButtonState {
+    /// Debounced level
+    pressed: bool,
+    /// Last level that was read
+    raw: bool,
+    /// When `raw` last changed
+    raw_since: u32,
+    /// When the button was pressed
+    pressed_at: u32,
+    long_sent: bool,
+    /// When the last long press or repeat was sent
+    last_repeat: u32,
+}
+
+/// Debouncing state machine for the five front buttons
+///
+/// Keeps up to `N` events in a queue. When the queue is full the oldest event
+/// is dropped, see [`ButtonEvents::dropped`].
+pub struct ButtonEvents<const N: usize = 16> {
+    timings: ButtonTimings,
+    buttons: [ButtonState; 5],
+    chord: ButtonSet,
+    queue: [Option<ButtonEvent>; N],
+    head: usize,
+    len: usize,
+    dropped: u32,
+}
+
+impl<const N: usize> Default for ButtonEvents<N> {
+    fn default() -> Self {
+        ButtonEvents::new(ButtonTimings::default())
+    }
+}
+
+impl<const N: usize> ButtonEvents<N> {
+    pub fn new(timings: ButtonTimings) -> Self {
+        ButtonEvents {
+            timings,
+            buttons: [ButtonState::default(); 5],
+            chord: ButtonSet::default(),
+            queue: [None; N],
+            head: 0,
+            len: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Changes the timings, buttons that are held down keep their state
+    pub fn set_timings(&mut self, timings: ButtonTimings) {
+        self.timings = timings;
+    }
+
+    /// Feeds a new snapshot of the inputs, read at `now` milliseconds
+    pub fn update(&mut self, inputs: Inputs, now: u32) {
+        for button in Button::ALL {
+            if let Some(event) = self.update_button(button, inputs.is_pressed(button), now) {
+                self.push(event);
+            }
+        }
+
+        let held = Button::ALL
+            .into_iter()
+            .filter(|button| self.is_pressed(*button))
+            .fold(ButtonSet::default(), ButtonSet::with);
+        if held != self.chord {
+            self.chord = held;
+            if held.len() >= 2 {
+                self.push(ButtonEvent::Chord(held));
+            }
+        }
+    }
+
+    /// Takes the oldest event out of the queue
+    pub fn pop(&mut self) -> Option<ButtonEvent> {
+        if self.len == 0 {
+            return None;
+        }
+        let event = self.queue[self.head].take();
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        event
+    }
+
+    /// Whether the button is held down, after debouncing
+    pub fn is_pressed(&self, button: Button) -> bool {
+        self.buttons[button.bit() as usize].pressed
+    }
+
+    /// How many events were dropped because the queue was full
+    pub fn dropped(&self) -> u32 {
+        self.dropped
+    }
+
+    fn update_button(&mut self, button: Button, raw: bool, now: u32) -> Option<ButtonEvent> {
+        let timings = self.timings;
+        let state = &mut self.buttons[button.bit() as usize];
+        if raw != state.raw {
+            state.raw = raw;
+            state.raw_since = now;
+        }
+
+        if state.raw != state.pressed && now.wrapping_sub(state.raw_since) >= timings.debounce {
+            state.pressed = state.raw;
+            if state.pressed {
+                state.pressed_at = now;
+                state.long_sent = false;
+                return Some(ButtonEvent::Pressed(button));
+            }
+            return Some(ButtonEvent::Released(button));
+        }
+
+        if !state.pressed {
+            None
+        } else if !state.long_sent {
+            if now.wrapping_sub(state.pressed_at) < timings.long_press {
+                return None;
+            }
+            state.long_sent = true;
+            state.last_repeat = now;
+            Some(ButtonEvent::LongPress(button))
+        } else if timings.repeat_interval > 0
+            && now.wrapping_sub(state.last_repeat) >= timings.repeat_interval
+        {
+            state.last_repeat = now;
+            Some(ButtonEvent::Repeat(button))
+        } else {
+            None
+        }
+    }
+
+    fn push(&mut self, event: ButtonEvent) {
+        if N == 0 {
+            self.dropped += 1;
+            return;
+        }
+        if self.len == N {
+            // drop the oldest event to make room
+            self.head = (self.head + 1) % N;
+            self.len -= 1;
+            self.dropped += 1;
+        }
+        self.queue[(self.head + self.len) % N] = Some(event);
+        self.len += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn held(buttons: &[Button]) -> Inputs {
+        Inputs::from_register(buttons.iter().fold(0, |acc, b| acc | 1 << b.bit()))
+    }
+
+    fn expect<const N: usize>(events: &mut ButtonEvents<N>, expected: &[ButtonEvent]) {
+        for event in expected {
+            assert_eq!(events.pop(), Some(*event));
+        }
+        assert_eq!(events.pop(), None);
+    }
+
+    #[test]
+    fn bounces_are_ignored() {
+        let mut events = ButtonEvents::<16>::default();
+        events.update(held(&[Button::A]), 0);
+        events.update(held(&[]), 5);
+        events.update(held(&[Button::A]), 10);
+        events.update(held(&[Button::A]), 29);
+        expect(&mut events, &[]);
+        assert!(!events.is_pressed(Button::A));
+
+        events.update(held(&[Button::A]), 30);
+        expect(&mut events, &[ButtonEvent::Pressed(Button::A)]);
+        assert!(events.is_pressed(Button::A));
+
+        // a short glitch while held doesn't release it
+        events.update(held(&[]), 40);
+        events.update(held(&[Button::A]), 45);
+        events.update(held(&[Button::A]), 100);
+        expect(&mut events, &[]);
+        assert!(events.is_pressed(Button::A));
+    }
+
+    #[test]
+    fn press_and_release() {
+        let mut events = ButtonEvents::<16>::default();
+        events.update(held(&[Button::C]), 0);
+        events.update(held(&[Button::C]), 20);
+        expect(&mut events, &[ButtonEvent::Pressed(Button::C)]);
+        events.update(held(&[]), 100);
+        events.update(held(&[]), 119);
+        expect(&mut events, &[]);
+        events.update(held(&[]), 120);
+        expect(&mut events, &[ButtonEvent::Released(Button::C)]);
+        assert!(!events.is_pressed(Button::C));
+    }
+
+    #[test]
+    fn long_press_then_repeats() {
+        let mut events = ButtonEvents::<16>::default();
+        events.update(held(&[Button::B]), 0);
+        events.update(held(&[Button::B]), 20);
+        expect(&mut events, &[ButtonEvent::Pressed(Button::B)]);
+
+        // long press 800ms after the debounced press
+        events.update(held(&[Button::B]), 819);
+        expect(&mut events, &[]);
+        events.update(held(&[Button::B]), 820);
+        expect(&mut events, &[ButtonEvent::LongPress(Button::B)]);
+
+        // then a repeat every 200ms
+        events.update(held(&[Button::B]), 1019);
+        expect(&mut events, &[]);
+        events.update(held(&[Button::B]), 1020);
+        events.update(held(&[Button::B]), 1100);
+        events.update(held(&[Button::B]), 1220);
+        expect(
+            &mut events,
+            &[
+                ButtonEvent::Repeat(Button::B),
+                ButtonEvent::Repeat(Button::B),
+            ],
+        );
+
+        events.update(held(&[]), 1300);
+        events.update(held(&[]), 1320);
+        expect(&mut events, &[ButtonEvent::Released(Button::B)]);
+    }
+
+    #[test]
+    fn repeats_can_be_turned_off() {
+        let mut events = ButtonEvents::<16>::new(ButtonTimings {
+            repeat_interval: 0,
+            ..ButtonTimings::default()
+        });
+        events.update(held(&[Button::E]), 0);
+        events.update(held(&[Button::E]), 20);
+        events.update(held(&[Button::E]), 820);
+        events.update(held(&[Button::E]), 5000);
+        expect(
+            &mut events,
+            &[
+                ButtonEvent::Pressed(Button::E),
+                ButtonEvent::LongPress(Button::E),
+            ],
+        );
+    }
+
+    #[test]
+    fn chords_follow_the_held_buttons() {
+        let a_b = ButtonSet::default().with(Button::A).with(Button::B);
+        let a_b_d = a_b.with(Button::D);
+
+        let mut events = ButtonEvents::<16>::default();
+        events.update(held(&[Button::A, Button::B]), 0);
+        events.update(held(&[Button::A, Button::B]), 20);
+        expect(
+            &mut events,
+            &[
+                ButtonEvent::Pressed(Button::A),
+                ButtonEvent::Pressed(Button::B),
+                ButtonEvent::Chord(a_b),
+            ],
+        );
+
+        events.update(held(&[Button::A, Button::B, Button::D]), 30);
+        events.update(held(&[Button::A, Button::B, Button::D]), 50);
+        expect(
+            &mut events,
+            &[ButtonEvent::Pressed(Button::D), ButtonEvent::Chord(a_b_d)],
+        );
+
+        events.update(held(&[Button::A, Button::B]), 60);
+        events.update(held(&[Button::A, Button::B]), 80);
+        expect(
+            &mut events,
+            &[ButtonEvent::Released(Button::D), ButtonEvent::Chord(a_b)],
+        );
+
+        // a single button left isn't a chord
+        events.update(held(&[Button::A]), 90);
+        events.update(held(&[Button::A]), 110);
+        expect(&mut events, &[ButtonEvent::Released(Button::B)]);
+    }
+
+    #[test]
+    fn full_queue_drops_the_oldest() {
+        let all = Button::ALL
+            .into_iter()
+            .fold(ButtonSet::default(), ButtonSet::with);
+
+        let mut events = ButtonEvents::<2>::default();
+        events.update(held(&Button::ALL), 0);
+        events.update(held(&Button::ALL), 20);
+        assert_eq!(events.dropped(), 4);
+        expect(
+            &mut events,
+            &[ButtonEvent::Pressed(Button::E), ButtonEvent::Chord(all)],
+        );
+
+        // room is made again after popping
+        events.update(held(&[]), 30);
+        events.update(held(&[]), 50);
+        assert_eq!(events.dropped(), 7);
+        expect(
+            &mut events,
+            &[
+                ButtonEvent::Released(Button::D),
+                ButtonEvent::Released(Button::E),
+            ],
+        );
+    }
+
+    #[test]
+    fn timestamps_wrap_around() {
+        let start = u32::MAX - 9;
+        let mut events = ButtonEvents::<16>::default();
+        events.update(held(&[Button::A]), start);
+        events.update(held(&[Button::A]), start.wrapping_add(19));
+        expect(&mut events, &[]);
+        events.update(held(&[Button::A]), start.wrapping_add(20));
+        expect(&mut events, &[ButtonEvent::Pressed(Button::A)]);
+
+        events.update(held(&[Button::A]), start.wrapping_add(819));
+        expect(&mut events, &[]);
+        events.update(held(&[Button::A]), start.wrapping_add(820));
+        events.update(held(&[Button::A]), start.wrapping_add(1020));
+        expect(
+            &mut events,
+            &[
+                ButtonEvent::LongPress(Button::A),
+                ButtonEvent::Repeat(Button::A),
+            ],
+        );
+    }
+}
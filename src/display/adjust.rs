@@ -0,0 +1,280 @@
+//! Color adjustments applied before quantisation and dithering
+//!
+//! Photos tend to look flat on the panel. An [`ImageAdjust`] tunes gamma,
+//! contrast, brightness and saturation with fixed point math, and an
+//! [`AdjustDrawTarget`] applies it to everything drawn through it. As the
+//! adapter is cheap to create, every draw call can use its own settings, e.g.
+//! a boosted photo next to UI elements drawn as they are.
+use embedded_graphics::{pixelcolor::Rgb888, prelude::*, primitives::Rectangle};
+
+/// 1.0 in the 8.8 fixed point values used for the factors
+pub const ONE: u16 = 256;
+
+/// Gamma, contrast, brightness and saturation settings
+///
+/// Factors are 8.8 fixed point, so [`ONE`] (256) leaves the image as it is.
+/// Gamma, contrast and brightness are per channel and combined into a lookup
+/// table by [`build`](Self::build), applied in that order. Saturation mixes
+/// every pixel with its luma before the lookup.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ImageAdjust {
+    gamma: u16,
+    contrast: u16,
+    brightness: i16,
+    saturation: u16,
+}
+
+impl Default for ImageAdjust {
+    fn default() -> Self {
+        ImageAdjust::new()
+    }
+}
+
+impl ImageAdjust {
+    /// Settings that leave the image as it is
+    pub const fn new() -> ImageAdjust {
+        ImageAdjust {
+            gamma: ONE,
+            contrast: ONE,
+            brightness: 0,
+            saturation: ONE,
+        }
+    }
+
+    /// Raises every channel to the power of `gamma / 256`
+    ///
+    /// Values below [`ONE`] lift the midtones, above darken them
+    pub const fn gamma(mut self, gamma: u16) -> ImageAdjust {
+        self.gamma = gamma;
+        self
+    }
+
+    /// Scales every channel's distance from the middle grey by `contrast / 256`
+    pub const fn contrast(mut self, contrast: u16) -> ImageAdjust {
+        self.contrast = contrast;
+        self
+    }
+
+    /// Adds `brightness` to every channel
+    pub const fn brightness(mut self, brightness: i16) -> ImageAdjust {
+        self.brightness = brightness;
+        self
+    }
+
+    /// Scales every pixel's distance from its luma by `saturation / 256`
+    ///
+    /// 0 turns the image gray, values above [`ONE`] boost the colors
+    pub const fn saturation(mut self, saturation: u16) -> ImageAdjust {
+        self.saturation = saturation;
+        self
+    }
+
+    /// Computes the lookup table for applying the settings to colors
+    pub fn build(&self) -> AdjustTable {
+        let mut lut = [0; 256];
+        for (value, entry) in lut.iter_mut().enumerate() {
+            // 0..=255 to 0..=65536
+            let x = ((value as u32) << 16) / 255;
+            let x = i64::from(pow_q16(x, self.gamma));
+            let x = (x - 32768) * i64::from(self.contrast) / i64::from(ONE) + 32768;
+            let x = (x * 255 + 32768) >> 16;
+            *entry = (x + i64::from(self.brightness)).clamp(0, 255) as u8;
+        }
+        AdjustTable {
+            lut,
+            saturation: self.saturation,
+        }
+    }
+}
+
+/// [`ImageAdjust`] settings ready to be applied, see [`ImageAdjust::build`]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct AdjustTable {
+    lut: [u8; 256],
+    saturation: u16,
+}
+
+impl Default for AdjustTable {
+    fn default() -> Self {
+        ImageAdjust::new().build()
+    }
+}
+
+impl AdjustTable {
+    /// Applies the adjustments to a color
+    pub fn apply(&self, color: Rgb888) -> Rgb888 {
+        let (mut r, mut g, mut b) = (color.r(), color.g(), color.b());
+        if self.saturation != ONE {
+            let luma = (77 * i32::from(r) + 150 * i32::from(g) + 29 * i32::from(b)) >> 8;
+            let saturate = |channel: u8| {
+                let diff = i32::from(channel) - luma;
+                (luma + diff * i32::from(self.saturation) / i32::from(ONE)).clamp(0, 255) as u8
+            };
+            (r, g, b) = (saturate(r), saturate(g), saturate(b));
+        }
+        Rgb888::new(
+            self.lut[r as usize],
+            self.lut[g as usize],
+            self.lut[b as usize],
+        )
+    }
+}
+
+/// `x` (0..=65536 for 0.0..=1.0) to the power of `exponent` (8.8 fixed point)
+fn pow_q16(x: u32, exponent: u16) -> u32 {
+    const UNIT: u64 = 1 << 16;
+    let x = u64::from(x).min(UNIT);
+    let mut result = UNIT;
+
+    // whole part by multiplying
+    for _ in 0..exponent >> 8 {
+        result = (result * x) >> 16;
+    }
+
+    // fractional bits from repeated square roots: x^(1/2), x^(1/4), ...
+    let mut root = x;
+    for bit in (0..8).rev() {
+        root = (root << 16).isqrt();
+        if exponent & (1 << bit) != 0 {
+            result = (result * root) >> 16;
+        }
+    }
+    result as u32
+}
+
+/// Draw target adapter that adjusts [`Rgb888`] colors before passing them on
+///
+/// Put it in front of a color converting adapter such as
+/// [`DitheringDrawTarget`](super::dither::DitheringDrawTarget).
+pub struct AdjustDrawTarget<'a, D> {
+    target: &'a mut D,
+    adjust: AdjustTable,
+}
+
+impl<'a, D> AdjustDrawTarget<'a, D>
+where
+    D: DrawTarget<Color = Rgb888>,
+{
+    /// Wraps the target, building the lookup table once
+    pub fn new(target: &'a mut D, adjust: ImageAdjust) -> Self {
+        AdjustDrawTarget {
+            target,
+            adjust: adjust.build(),
+        }
+    }
+}
+
+impl<D> Dimensions for AdjustDrawTarget<'_, D>
+where
+    D: DrawTarget<Color = Rgb888>,
+{
+    fn bounding_box(&self) -> Rectangle {
+        self.target.bounding_box()
+    }
+}
+
+impl<D> DrawTarget for AdjustDrawTarget<'_, D>
+where
+    D: DrawTarget<Color = Rgb888>,
+{
+    type Color = Rgb888;
+    type Error = D::Error;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        let adjust = &self.adjust;
+        self.target.draw_iter(
+            pixels
+                .into_iter()
+                .map(|Pixel(point, color)| Pixel(point, adjust.apply(color))),
+        )
+    }
+
+    fn fill_contiguous<I>(&mut self, area: &Rectangle, colors: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Self::Color>,
+    {
+        let adjust = &self.adjust;
+        self.target
+            .fill_contiguous(area, colors.into_iter().map(|color| adjust.apply(color)))
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        self.target.fill_solid(area, self.adjust.apply(color))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UNIT: u32 = 1 << 16;
+
+    fn lut(adjust: ImageAdjust) -> [u8; 256] {
+        adjust.build().lut
+    }
+
+    #[test]
+    fn powers() {
+        for exponent in [0, ONE / 2, ONE, 2 * ONE, 3 * ONE + ONE / 4] {
+            assert_eq!(pow_q16(UNIT, exponent), UNIT, "{exponent}");
+        }
+        assert_eq!(pow_q16(0, ONE), 0);
+        assert_eq!(pow_q16(12345, 0), UNIT);
+        assert_eq!(pow_q16(12345, ONE), 12345);
+        assert_eq!(pow_q16(UNIT / 2, 2 * ONE), UNIT / 4);
+        assert_eq!(pow_q16(UNIT / 4, ONE / 2), UNIT / 2);
+        // 0.5^2.5 = 0.17678
+        assert!(pow_q16(UNIT / 2, 2 * ONE + ONE / 2).abs_diff(11585) <= 2);
+    }
+
+    #[test]
+    fn default_is_the_identity() {
+        for (value, entry) in lut(ImageAdjust::default()).iter().enumerate() {
+            assert_eq!(usize::from(*entry), value);
+        }
+        let color = Rgb888::new(12, 200, 99);
+        assert_eq!(AdjustTable::default().apply(color), color);
+    }
+
+    #[test]
+    fn gamma_two_squares() {
+        for (value, entry) in lut(ImageAdjust::new().gamma(2 * ONE)).iter().enumerate() {
+            let expected = (value * value + 127) / 255;
+            assert!(usize::from(*entry).abs_diff(expected) <= 1, "{value}");
+        }
+    }
+
+    #[test]
+    fn no_contrast_is_mid_gray() {
+        assert!(lut(ImageAdjust::new().contrast(0))
+            .iter()
+            .all(|&entry| entry == 128));
+    }
+
+    #[test]
+    fn brightness_clamps() {
+        let brighter = lut(ImageAdjust::new().brightness(100));
+        assert_eq!(brighter[0], 100);
+        assert_eq!(brighter[155], 255);
+        assert_eq!(brighter[255], 255);
+        let darker = lut(ImageAdjust::new().brightness(-100));
+        assert_eq!(darker[0], 0);
+        assert_eq!(darker[100], 0);
+        assert_eq!(darker[255], 155);
+    }
+
+    #[test]
+    fn saturation_mixes_with_luma() {
+        let color = Rgb888::new(200, 100, 50);
+        // luma = (77 * 200 + 150 * 100 + 29 * 50) >> 8 = 124
+        let gray = ImageAdjust::new().saturation(0).build();
+        assert_eq!(gray.apply(color), Rgb888::new(124, 124, 124));
+        let boosted = ImageAdjust::new().saturation(2 * ONE).build();
+        assert_eq!(boosted.apply(color), Rgb888::new(255, 76, 0));
+        let half = ImageAdjust::new().saturation(ONE / 2).build();
+        assert_eq!(half.apply(color), Rgb888::new(162, 112, 87));
+    }
+}
@@ -1,4 +1,6 @@
-use embedded_graphics::pixelcolor::{BinaryColor, PixelColor};
+use embedded_graphics::pixelcolor::{BinaryColor, GrayColor, PixelColor, RgbColor};
+
+use super::oklab;
 
 /// When trying to parse u8 to one of the color types
 #[derive(Debug, PartialEq, Eq)]
@@ -56,112 +58,318 @@ impl From<OctColor> for embedded_graphics::pixelcolor::Rgb888 {
     }
 }
 
+/// Picks the closest of the idealised primaries in [`Palette::SATURATED`], use
+/// [`Palette::nearest`] with [`Palette::DESATURATED`] to match the panel's inks
 impl From<embedded_graphics::pixelcolor::Rgb565> for OctColor {
     fn from(p: embedded_graphics::pixelcolor::Rgb565) -> OctColor {
-        use embedded_graphics::prelude::RgbColor;
-        let colors = [
-            OctColor::Black,
-            OctColor::White,
-            OctColor::Green,
-            OctColor::Blue,
-            OctColor::Red,
-            OctColor::Yellow,
-            OctColor::Orange,
-            OctColor::HiZ,
-        ];
-        // if the user has already mapped to the right color space, it will just be in the list
-        if let Some(found) = colors.iter().find(|c| c.rgb() == (p.r(), p.g(), p.b())) {
-            return *found;
-        }
-
-        // This is not ideal but just pick the nearest color
-        *colors
-            .iter()
-            .map(|c| (c, c.rgb()))
-            .map(|(c, (r, g, b))| {
-                let dist = (i32::from(r) - i32::from(p.r())).pow(2)
-                    + (i32::from(g) - i32::from(p.g())).pow(2)
-                    + (i32::from(b) - i32::from(p.b())).pow(2);
-                (c, dist)
-            })
-            .min_by_key(|(_c, dist)| *dist)
-            .map(|(c, _)| c)
-            .unwrap_or(&OctColor::White)
+        Palette::default().quantize(p)
     }
 }
 
+/// Picks the closest of the idealised primaries in [`Palette::SATURATED`], use
+/// [`Palette::nearest`] with [`Palette::DESATURATED`] to match the panel's inks
 impl From<embedded_graphics::pixelcolor::Rgb555> for OctColor {
     fn from(p: embedded_graphics::pixelcolor::Rgb555) -> OctColor {
-        use embedded_graphics::prelude::RgbColor;
-        let colors = [
-            OctColor::Black,
-            OctColor::White,
-            OctColor::Green,
-            OctColor::Blue,
-            OctColor::Red,
-            OctColor::Yellow,
-            OctColor::Orange,
-            OctColor::HiZ,
-        ];
-        // if the user has already mapped to the right color space, it will just be in the list
-        if let Some(found) = colors.iter().find(|c| c.rgb() == (p.r(), p.g(), p.b())) {
-            return *found;
-        }
-
-        // This is not ideal but just pick the nearest color
-        *colors
-            .iter()
-            .map(|c| (c, c.rgb()))
-            .map(|(c, (r, g, b))| {
-                let dist = (i32::from(r) - i32::from(p.r())).pow(2)
-                    + (i32::from(g) - i32::from(p.g())).pow(2)
-                    + (i32::from(b) - i32::from(p.b())).pow(2);
-                (c, dist)
-            })
-            .min_by_key(|(_c, dist)| *dist)
-            .map(|(c, _)| c)
-            .unwrap_or(&OctColor::White)
+        Palette::default().quantize(p)
     }
 }
 
+/// Picks the closest of the idealised primaries in [`Palette::SATURATED`], use
+/// [`Palette::nearest`] with [`Palette::DESATURATED`] to match the panel's inks
 impl From<embedded_graphics::pixelcolor::Rgb888> for OctColor {
     fn from(p: embedded_graphics::pixelcolor::Rgb888) -> OctColor {
-        use embedded_graphics::prelude::RgbColor;
-        let colors = [
-            OctColor::Black,
-            OctColor::White,
-            OctColor::Green,
-            OctColor::Blue,
-            OctColor::Red,
-            OctColor::Yellow,
-            OctColor::Orange,
-            OctColor::HiZ,
-        ];
+        Palette::default().quantize(p)
+    }
+}
+
+impl From<embedded_graphics::pixelcolor::Gray2> for OctColor {
+    fn from(p: embedded_graphics::pixelcolor::Gray2) -> OctColor {
+        from_gray(p)
+    }
+}
+
+impl From<embedded_graphics::pixelcolor::Gray4> for OctColor {
+    fn from(p: embedded_graphics::pixelcolor::Gray4) -> OctColor {
+        from_gray(p)
+    }
+}
+
+impl From<embedded_graphics::pixelcolor::Gray8> for OctColor {
+    fn from(p: embedded_graphics::pixelcolor::Gray8) -> OctColor {
+        from_gray(p)
+    }
+}
+
+/// Shades of gray become black or white, whichever is closer
+fn from_gray<C: GrayColor>(p: C) -> OctColor {
+    if u16::from(p.luma()) * 2 > u16::from(C::WHITE.luma()) {
+        OctColor::White
+    } else {
+        OctColor::Black
+    }
+}
+
+/// The RGB values colors get matched against when converting to [OctColor]
+///
+/// The panel's inks are a lot duller than pure primaries, so matching against
+/// the measured values picks better colors and lets dithering compensate for
+/// the difference. The values come from Pimoroni's inky driver
+/// https://github.com/pimoroni/inky/blob/main/library/inky/inky_uc8159.py
+/// MIT License: https://github.com/pimoroni/inky/blob/main/LICENSE
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Palette {
+    /// RGB values indexed by the color's nibble
+    colors: [(u8, u8, u8); 8],
+    /// The same colors in OKLab, precomputed for [ColorDistance::OkLab]
+    lab: [[i32; 3]; 8],
+    distance: ColorDistance,
+    candidates: ColorSet,
+}
+
+/// How the difference between two colors is measured when looking for the closest one
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ColorDistance {
+    /// Squared distance between the RGB values, cheap but e.g. picks orange
+    /// over red and green over blue in ways that look wrong
+    #[default]
+    Rgb,
+    /// Squared distance in the OKLab color space, which follows how different
+    /// the colors look. Converting each pixel costs a few table lookups and
+    /// multiplications, the inks are converted once with the palette.
+    OkLab,
+}
+
+/// The idealised primaries, so pure colors map to the ink of the same name
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::SATURATED
+    }
+}
+
+impl Palette {
+    /// Idealised primaries, the same as [OctColor::rgb]
+    pub const SATURATED: Palette = Palette::new([
+        (0x00, 0x00, 0x00),
+        (0xff, 0xff, 0xff),
+        (0x00, 0xff, 0x00),
+        (0x00, 0x00, 0xff),
+        (0xff, 0x00, 0x00),
+        (0xff, 0xff, 0x00),
+        (0xff, 0x80, 0x00),
+        (0x80, 0x80, 0x80),
+    ]);
+
+    /// Measured colors of the inks on the panel
+    pub const DESATURATED: Palette = Palette::new([
+        (57, 48, 57),
+        (255, 255, 255),
+        (58, 91, 70),
+        (61, 59, 94),
+        (156, 72, 75),
+        (208, 181, 72),
+        (177, 106, 73),
+        (0x80, 0x80, 0x80),
+    ]);
+
+    /// Creates a palette from RGB values in the order of the colors' nibbles
+    pub const fn new(colors: [(u8, u8, u8); 8]) -> Palette {
+        let mut lab = [[0; 3]; 8];
+        let mut i = 0;
+        while i < colors.len() {
+            lab[i] = oklab::oklab(colors[i].0, colors[i].1, colors[i].2);
+            i += 1;
+        }
+        Palette {
+            colors,
+            lab,
+            distance: ColorDistance::Rgb,
+            candidates: ColorSet::INKS,
+        }
+    }
+
+    /// Uses a different way of measuring the difference between colors
+    pub const fn with_distance(mut self, distance: ColorDistance) -> Palette {
+        self.distance = distance;
+        self
+    }
+
+    /// Blends between the measured (0) and the idealised (255) colors
+    ///
+    /// Pimoroni's driver defaults to about halfway, 128
+    pub const fn blended(saturation: u8) -> Palette {
+        const fn blend(desaturated: u8, saturated: u8, saturation: u8) -> u8 {
+            ((desaturated as u16 * (255 - saturation) as u16
+                + saturated as u16 * saturation as u16
+                + 127)
+                / 255) as u8
+        }
+        let mut colors = Palette::DESATURATED.colors;
+        let mut i = 0;
+        while i < colors.len() {
+            let (d, s) = (colors[i], Palette::SATURATED.colors[i]);
+            colors[i] = (
+                blend(d.0, s.0, saturation),
+                blend(d.1, s.1, saturation),
+                blend(d.2, s.2, saturation),
+            );
+            i += 1;
+        }
+        Palette::new(colors)
+    }
+
+    /// Restricts the colors that conversions may pick
+    ///
+    /// Defaults to [ColorSet::INKS]
+    pub const fn with_candidates(mut self, candidates: ColorSet) -> Palette {
+        self.candidates = candidates;
+        self
+    }
+
+    /// The colors that conversions may pick
+    pub fn candidates(&self) -> ColorSet {
+        self.candidates
+    }
+
+    /// The RGB values of a color in this palette
+    pub fn rgb(&self, color: OctColor) -> (u8, u8, u8) {
+        self.colors[color.get_nibble() as usize]
+    }
+
+    /// Picks the candidate color closest to the given 8 bit RGB values
+    ///
+    /// Falls back to white if there are no candidates
+    pub fn nearest(&self, r: u8, g: u8, b: u8) -> OctColor {
+        let candidates = self.candidates.iter();
         // if the user has already mapped to the right color space, it will just be in the list
-        if let Some(found) = colors.iter().find(|c| c.rgb() == (p.r(), p.g(), p.b())) {
-            return *found;
+        if let Some(found) = candidates.clone().find(|c| self.rgb(*c) == (r, g, b)) {
+            return found;
+        }
+
+        if self.distance == ColorDistance::OkLab {
+            let lab = oklab::oklab(r, g, b);
+            return candidates
+                .min_by_key(|c| oklab::distance(&self.lab[c.get_nibble() as usize], &lab))
+                .unwrap_or(OctColor::White);
         }
 
         // This is not ideal but just pick the nearest color
-        *colors
-            .iter()
-            .map(|c| (c, c.rgb()))
-            .map(|(c, (r, g, b))| {
-                let dist = (i32::from(r) - i32::from(p.r())).pow(2)
-                    + (i32::from(g) - i32::from(p.g())).pow(2)
-                    + (i32::from(b) - i32::from(p.b())).pow(2);
+        candidates
+            .map(|c| (c, self.rgb(c)))
+            .map(|(c, (cr, cg, cb))| {
+                let dist = (i32::from(cr) - i32::from(r)).pow(2)
+                    + (i32::from(cg) - i32::from(g)).pow(2)
+                    + (i32::from(cb) - i32::from(b)).pow(2);
                 (c, dist)
             })
             .min_by_key(|(_c, dist)| *dist)
             .map(|(c, _)| c)
-            .unwrap_or(&OctColor::White)
+            .unwrap_or(OctColor::White)
+    }
+
+    /// Converts any RGB color to the closest color of this palette
+    pub fn quantize<C: RgbColor>(&self, color: C) -> OctColor {
+        let (r, g, b) = rgb888(color);
+        self.nearest(r, g, b)
+    }
+}
+
+/// A set of [OctColor]s, e.g. the ones a [Palette] may convert to
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ColorSet(u8);
+
+impl Default for ColorSet {
+    fn default() -> Self {
+        ColorSet::INKS
+    }
+}
+
+impl ColorSet {
+    /// The seven inks. Leaves out [OctColor::HiZ], the panel's "clean" state,
+    /// which shows up as an unpredictable muddy color.
+    pub const INKS: ColorSet = ColorSet(0x7f);
+    /// Every color including [OctColor::HiZ]
+    pub const ALL: ColorSet = ColorSet(0xff);
+    /// Black and white only
+    pub const BLACK_WHITE: ColorSet = ColorSet::empty()
+        .with(OctColor::Black)
+        .with(OctColor::White);
+    /// Black, white and red, for high contrast signage
+    pub const BLACK_WHITE_RED: ColorSet = ColorSet::BLACK_WHITE.with(OctColor::Red);
+
+    /// A set without any colors
+    pub const fn empty() -> ColorSet {
+        ColorSet(0)
+    }
+
+    /// A set of the given colors
+    pub const fn from_colors(colors: &[OctColor]) -> ColorSet {
+        let mut set = ColorSet::empty();
+        let mut i = 0;
+        while i < colors.len() {
+            set = set.with(colors[i]);
+            i += 1;
+        }
+        set
+    }
+
+    /// Adds a color to the set
+    pub const fn with(self, color: OctColor) -> ColorSet {
+        ColorSet(self.0 | 1 << color as u8)
     }
+
+    /// Removes a color from the set
+    pub const fn without(self, color: OctColor) -> ColorSet {
+        ColorSet(self.0 & !(1 << color as u8))
+    }
+
+    /// Whether the color is in the set
+    pub const fn contains(self, color: OctColor) -> bool {
+        self.0 & (1 << color as u8) != 0
+    }
+
+    /// The colors in the set, ordered by their nibble
+    pub fn iter(self) -> impl Iterator<Item = OctColor> + Clone {
+        (0..8u8)
+            .filter(move |nibble| self.0 & (1 << nibble) != 0)
+            .filter_map(|nibble| OctColor::from_nibble(nibble).ok())
+    }
+}
+
+/// Scales the channels of any RGB color to 0..=255
+pub(crate) fn rgb888<C: RgbColor>(color: C) -> (u8, u8, u8) {
+    let scale = |value: u8, max: u8| {
+        if max == 255 {
+            value
+        } else {
+            (u16::from(value) * 255 / u16::from(max)) as u8
+        }
+    };
+    (
+        scale(color.r(), C::MAX_R),
+        scale(color.g(), C::MAX_G),
+        scale(color.b(), C::MAX_B),
+    )
 }
 
+/// Needed for `ImageRaw<OctColor>`, which can't fail. The panel only looks at
+/// the lower 3 bits, so nibbles 8 to 15 show up as the color of `nibble & 0x7`
+/// and are converted the same way. Use [`OctImage`](super::image::OctImage)
+/// to catch them instead.
 impl From<embedded_graphics::pixelcolor::raw::RawU4> for OctColor {
     fn from(b: embedded_graphics::pixelcolor::raw::RawU4) -> Self {
         use embedded_graphics::prelude::RawData;
-        OctColor::from_nibble(b.into_inner()).unwrap()
+        OctColor::from_nibble_lossy(b.into_inner())
+    }
+}
+
+impl TryFrom<u8> for OctColor {
+    type Error = OutOfColorRangeParseError;
+
+    fn try_from(nibble: u8) -> Result<Self, Self::Error> {
+        if nibble > 0xf {
+            return Err(OutOfColorRangeParseError(nibble));
+        }
+        OctColor::from_nibble(nibble)
     }
 }
 
@@ -180,7 +388,7 @@ impl OctColor {
     }
 
     ///Take the nibble (lower 4 bits) and convert to an OctColor if possible
-    pub fn from_nibble(nibble: u8) -> Result<OctColor, OutOfColorRangeParseError> {
+    pub const fn from_nibble(nibble: u8) -> Result<OctColor, OutOfColorRangeParseError> {
         match nibble & 0xf {
             0x00 => Ok(OctColor::Black),
             0x01 => Ok(OctColor::White),
@@ -193,6 +401,13 @@ impl OctColor {
             e => Err(OutOfColorRangeParseError(e)),
         }
     }
+    /// Take the lower 3 bits of the nibble, like the panel does
+    pub const fn from_nibble_lossy(nibble: u8) -> OctColor {
+        match OctColor::from_nibble(nibble & 0x7) {
+            Ok(color) => color,
+            Err(_) => OctColor::HiZ,
+        }
+    }
     ///Split the nibbles of a single byte and convert both to an OctColor if possible
     pub fn split_byte(byte: u8) -> Result<(OctColor, OctColor), OutOfColorRangeParseError> {
         let low = OctColor::from_nibble(byte & 0xf)?;
@@ -213,3 +428,83 @@ impl OctColor {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    const DISTANCES: [ColorDistance; 2] = [ColorDistance::Rgb, ColorDistance::OkLab];
+
+    #[test]
+    fn palette_colors_match_themselves() {
+        for palette in [Palette::SATURATED, Palette::DESATURATED] {
+            for distance in DISTANCES {
+                let palette = palette.with_distance(distance);
+                for color in ColorSet::INKS.iter() {
+                    let (r, g, b) = palette.rgb(color);
+                    assert_eq!(palette.nearest(r, g, b), color, "{:?}", distance);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn saturated_nearest() {
+        for distance in DISTANCES {
+            let palette = Palette::SATURATED.with_distance(distance);
+            assert_eq!(palette.nearest(250, 10, 10), OctColor::Red);
+            assert_eq!(palette.nearest(255, 140, 0), OctColor::Orange);
+            assert_eq!(palette.nearest(10, 10, 10), OctColor::Black);
+            assert_eq!(palette.nearest(240, 240, 240), OctColor::White);
+            assert_eq!(palette.nearest(20, 20, 230), OctColor::Blue);
+        }
+    }
+
+    #[test]
+    fn calibrated_nearest() {
+        let palette = Palette::DESATURATED;
+        assert_eq!(palette.nearest(255, 0, 0), OctColor::Red);
+        assert_eq!(palette.nearest(0, 0, 255), OctColor::Blue);
+        assert_eq!(palette.nearest(0, 255, 0), OctColor::Green);
+        assert_eq!(palette.nearest(255, 255, 0), OctColor::Yellow);
+        assert_eq!(palette.nearest(0, 0, 0), OctColor::Black);
+        assert_eq!(palette.nearest(255, 255, 255), OctColor::White);
+
+        // OKLab weighs lightness, so colors near the dull inks work best
+        let palette = palette.with_distance(ColorDistance::OkLab);
+        assert_eq!(palette.nearest(200, 40, 50), OctColor::Red);
+        assert_eq!(palette.nearest(60, 60, 110), OctColor::Blue);
+        assert_eq!(palette.nearest(50, 110, 70), OctColor::Green);
+        assert_eq!(palette.nearest(230, 200, 60), OctColor::Yellow);
+        assert_eq!(palette.nearest(190, 110, 60), OctColor::Orange);
+        assert_eq!(palette.nearest(40, 40, 40), OctColor::Black);
+        assert_eq!(palette.nearest(245, 245, 245), OctColor::White);
+    }
+
+    #[test]
+    fn nearest_only_picks_candidates() {
+        let palette = Palette::SATURATED.with_candidates(ColorSet::BLACK_WHITE);
+        assert_eq!(palette.nearest(255, 0, 0), OctColor::Black);
+        assert_eq!(palette.nearest(255, 255, 0), OctColor::White);
+        let palette = Palette::SATURATED.with_candidates(ColorSet::empty());
+        assert_eq!(palette.nearest(0, 0, 0), OctColor::White);
+    }
+
+    #[test]
+    fn blended_ends_are_the_palettes() {
+        const HALFWAY: Palette = Palette::blended(128);
+        assert_eq!(Palette::blended(0), Palette::DESATURATED);
+        assert_eq!(Palette::blended(255), Palette::SATURATED);
+        // (156 * 127 + 255 * 128 + 127) / 255 = 206
+        assert_eq!(HALFWAY.rgb(OctColor::Red), (206, 36, 37));
+    }
+
+    #[test]
+    fn rgb_conversions_use_the_primaries() {
+        use embedded_graphics::pixelcolor::{Rgb565, Rgb888};
+        assert_eq!(OctColor::from(Rgb888::new(255, 0, 0)), OctColor::Red);
+        assert_eq!(OctColor::from(Rgb565::new(0, 63, 0)), OctColor::Green);
+        assert_eq!(OctColor::from(Rgb888::new(255, 128, 0)), OctColor::Orange);
+    }
+}
@@ -1,13 +1,20 @@
-use embedded_graphics::prelude::*;
+use embedded_graphics::{
+    image::GetPixel,
+    prelude::*,
+    primitives::{rectangle, Rectangle},
+};
 
-use super::{color::OctColor, DEFAULT_BACKGROUND_COLOR, HEIGHT, WIDTH};
+use super::{
+    color::OctColor, recolor::ColorMap, traits::FrameSource, BUFFER_SIZE, DEFAULT_BACKGROUND_COLOR,
+    HEIGHT, WIDTH,
+};
 
 /// Full size buffer for use with the Inky Frame's Display
 /// Handles making inky frame compatible with Embedded Graphics
 /// Can also be manually constructed:
 /// `buffer: [DEFAULT_BACKGROUND_COLOR.get_byte_value(); WIDTH / 2 * HEIGHT]`
 pub struct InkyFrameDisplay {
-    buffer: [u8; WIDTH as usize / 2 * HEIGHT as usize],
+    buffer: [u8; BUFFER_SIZE],
     rotation: DisplayRotation,
 }
 
@@ -15,7 +22,7 @@ impl Default for InkyFrameDisplay {
     fn default() -> Self {
         InkyFrameDisplay {
             buffer: [OctColor::colors_byte(DEFAULT_BACKGROUND_COLOR, DEFAULT_BACKGROUND_COLOR);
-                WIDTH as usize / 2 * HEIGHT as usize],
+                BUFFER_SIZE],
             rotation: DisplayRotation::default(),
         }
     }
@@ -29,120 +36,299 @@ impl DrawTarget for InkyFrameDisplay {
     where
         I: IntoIterator<Item = Pixel<Self::Color>>,
     {
+        let rotation = self.rotation();
         for pixel in pixels {
-            self.draw_helper(WIDTH, HEIGHT, pixel)?;
+            draw_helper(self.get_mut_buffer(), WIDTH, HEIGHT, rotation, pixel)?;
         }
         Ok(())
     }
+
+    fn fill_contiguous<I>(&mut self, area: &Rectangle, colors: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Self::Color>,
+    {
+        let rotation = self.rotation();
+        fill_contiguous_helper(self.get_mut_buffer(), WIDTH, HEIGHT, rotation, area, colors);
+        Ok(())
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        let rotation = self.rotation();
+        fill_solid_helper(self.get_mut_buffer(), WIDTH, HEIGHT, rotation, area, color);
+        Ok(())
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        self.clear_buffer(color);
+        Ok(())
+    }
 }
 
 impl OriginDimensions for InkyFrameDisplay {
     fn size(&self) -> Size {
-        Size::new(WIDTH.into(), HEIGHT.into())
+        logical_size(WIDTH, HEIGHT, self.rotation())
+    }
+}
+
+impl GetPixel for InkyFrameDisplay {
+    type Color = OctColor;
+
+    fn pixel(&self, p: Point) -> Option<Self::Color> {
+        pixel_helper(self.buffer(), WIDTH, HEIGHT, self.rotation(), p)
+    }
+}
+
+impl FrameSource for InkyFrameDisplay {
+    fn panel_row(&self, y: u32, row: &mut [u8]) {
+        copy_panel_row(&self.buffer, y, row);
+    }
+}
+
+impl OctDisplay for InkyFrameDisplay {
+    fn buffer(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    fn get_mut_buffer(&mut self) -> &mut [u8] {
+        &mut self.buffer
+    }
+
+    fn set_rotation(&mut self, rotation: DisplayRotation) {
+        self.rotation = rotation;
+    }
+
+    fn rotation(&self) -> DisplayRotation {
+        self.rotation
     }
 }
 
+// Kept inherent so callers don't need `OctDisplay` in scope
 impl InkyFrameDisplay {
     /// Clears the buffer of the display with the chosen background color
     pub fn clear_buffer(&mut self, background_color: OctColor) {
-        for elem in self.get_mut_buffer().iter_mut() {
-            *elem = OctColor::colors_byte(background_color, background_color);
-        }
+        OctDisplay::clear_buffer(self, background_color);
     }
 
     /// Returns the buffer
     pub fn buffer(&self) -> &[u8] {
-        &self.buffer
+        OctDisplay::buffer(self)
+    }
+
+    /// Sets the rotation of the display
+    pub fn set_rotation(&mut self, rotation: DisplayRotation) {
+        OctDisplay::set_rotation(self, rotation);
+    }
+}
+
+/// Display buffer borrowed from caller-provided storage
+///
+/// Behaves like [`InkyFrameDisplay`] but doesn't own its buffer, so the
+/// buffer can be placed wherever the application needs it (a `static` in a
+/// specific linker section, external PSRAM, a shared memory pool, ...).
+/// The slice has to be exactly [`BUFFER_SIZE`] bytes long.
+pub struct InkyFrameDisplayRef<'a> {
+    buffer: &'a mut [u8],
+    rotation: DisplayRotation,
+}
+
+impl<'a> InkyFrameDisplayRef<'a> {
+    /// Wraps the buffer, keeping its current contents
+    pub fn new(buffer: &'a mut [u8]) -> Result<Self, InvalidBufferLengthError> {
+        if buffer.len() != BUFFER_SIZE {
+            return Err(InvalidBufferLengthError(buffer.len()));
+        }
+        Ok(InkyFrameDisplayRef {
+            buffer,
+            rotation: DisplayRotation::default(),
+        })
+    }
+
+    /// Wraps the buffer and clears it with the chosen background color
+    pub fn new_cleared(
+        buffer: &'a mut [u8],
+        background_color: OctColor,
+    ) -> Result<Self, InvalidBufferLengthError> {
+        let mut display = Self::new(buffer)?;
+        display.clear_buffer(background_color);
+        Ok(display)
+    }
+}
+
+impl DrawTarget for InkyFrameDisplayRef<'_> {
+    type Color = OctColor;
+    type Error = core::convert::Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for pixel in pixels {
+            draw_helper(self.buffer, WIDTH, HEIGHT, self.rotation, pixel)?;
+        }
+        Ok(())
+    }
+
+    fn fill_contiguous<I>(&mut self, area: &Rectangle, colors: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Self::Color>,
+    {
+        fill_contiguous_helper(self.buffer, WIDTH, HEIGHT, self.rotation, area, colors);
+        Ok(())
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        fill_solid_helper(self.buffer, WIDTH, HEIGHT, self.rotation, area, color);
+        Ok(())
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        self.clear_buffer(color);
+        Ok(())
+    }
+}
+
+impl OriginDimensions for InkyFrameDisplayRef<'_> {
+    fn size(&self) -> Size {
+        logical_size(WIDTH, HEIGHT, self.rotation)
+    }
+}
+
+impl GetPixel for InkyFrameDisplayRef<'_> {
+    type Color = OctColor;
+
+    fn pixel(&self, p: Point) -> Option<Self::Color> {
+        pixel_helper(self.buffer, WIDTH, HEIGHT, self.rotation, p)
+    }
+}
+
+impl FrameSource for InkyFrameDisplayRef<'_> {
+    fn panel_row(&self, y: u32, row: &mut [u8]) {
+        copy_panel_row(self.buffer, y, row);
+    }
+}
+
+impl OctDisplay for InkyFrameDisplayRef<'_> {
+    fn buffer(&self) -> &[u8] {
+        self.buffer
     }
 
-    /// Returns a mutable buffer
     fn get_mut_buffer(&mut self) -> &mut [u8] {
-        &mut self.buffer
+        self.buffer
     }
 
-    /// Sets the rotation of the display
-    pub fn set_rotation(&mut self, rotation: DisplayRotation) {
+    fn set_rotation(&mut self, rotation: DisplayRotation) {
         self.rotation = rotation;
     }
 
-    /// Get the current rotation of the display
     fn rotation(&self) -> DisplayRotation {
         self.rotation
     }
+}
+
+/// When a caller-provided buffer doesn't have the length the display needs
+#[derive(Debug, PartialEq, Eq)]
+pub struct InvalidBufferLengthError(pub usize);
+impl core::fmt::Display for InvalidBufferLengthError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "Buffer length {} doesn't match the display buffer size {}",
+            self.0, BUFFER_SIZE
+        )
+    }
+}
+
+/// Iterator over the colors stored in a display buffer, see [`OctDisplay`]
+/// for how nibbles that aren't a color are read
+pub struct BufferColors<'a> {
+    buffer: &'a [u8],
+    width: u32,
+    height: u32,
+    rotation: DisplayRotation,
+    area: Rectangle,
+    points: rectangle::Points,
+}
 
-    /// Helperfunction for the Embedded Graphics draw trait
-    fn draw_helper(
-        &mut self,
+impl<'a> BufferColors<'a> {
+    fn new(
+        buffer: &'a [u8],
         width: u32,
         height: u32,
-        pixel: Pixel<OctColor>,
-    ) -> Result<(), core::convert::Infallible> {
-        let rotation = self.rotation();
-        let buffer = self.get_mut_buffer();
-
-        let Pixel(point, color) = pixel;
-        if outside_display(point, width, height, rotation) {
-            return Ok(());
+        rotation: DisplayRotation,
+        area: &Rectangle,
+    ) -> Self {
+        let area = area.intersection(&Rectangle::new(
+            Point::zero(),
+            logical_size(width, height, rotation),
+        ));
+        BufferColors {
+            buffer,
+            width,
+            height,
+            rotation,
+            area,
+            points: area.points(),
         }
+    }
+
+    /// The area that is iterated over, after clipping to the display
+    pub fn area(&self) -> Rectangle {
+        self.area
+    }
+}
 
-        // Give us index inside the buffer and the bit-position in that u8 which needs to be changed
-        let (index, upper) =
-            find_oct_position(point.x as u32, point.y as u32, width, height, rotation);
-        let index = index as usize;
+impl Iterator for BufferColors<'_> {
+    type Item = OctColor;
 
-        // "Draw" the Pixel on that bit
-        let (mask, color_nibble) = if upper {
-            (0x0f, color.get_nibble() << 4)
-        } else {
-            (0xf0, color.get_nibble())
-        };
-
-        match buffer.get_mut(index) {
-            None => {
-                #[cfg(feature = "defmt")]
-                defmt::warn!(
-                    "index out of buffer, {} - point ({}, {})",
-                    index,
-                    point.x,
-                    point.y
-                );
-                ()
-            }
-            Some(i) => {
-                *i = (*i & mask) | color_nibble;
-            }
-        }
-        Ok(())
+    fn next(&mut self) -> Option<Self::Item> {
+        let point = self.points.next()?;
+        let (index, upper) = find_oct_position(
+            point.x as u32,
+            point.y as u32,
+            self.width,
+            self.height,
+            self.rotation,
+        );
+        let byte = self.buffer.get(index as usize).copied().unwrap_or(0x77);
+        let nibble = if upper { byte >> 4 } else { byte };
+        Some(OctColor::from_nibble_lossy(nibble))
     }
 }
 
 /// Displayrotation
-#[derive(Clone, Copy)]
+///
+/// [`Rotate90`](Self::Rotate90) and [`Rotate270`](Self::Rotate270) swap the
+/// width and height the displays report through `OriginDimensions::size`.
+#[derive(Clone, Copy, Default)]
 pub enum DisplayRotation {
     /// No rotation
     Rotate0,
     /// Rotate by 90 degrees clockwise
     Rotate90,
     /// Rotate by 180 degrees clockwise
+    // default to 180 so (0,0) is top left of display
+    #[default]
     Rotate180,
     /// Rotate 270 degrees clockwise
     Rotate270,
 }
 
-impl Default for DisplayRotation {
-    fn default() -> Self {
-        // default to 180 so (0,0) is top left of display
-        DisplayRotation::Rotate180
-    }
-}
-
 /// Necessary traits for all displays to implement for drawing
 ///
 /// Adds support for:
 /// - Drawing (With the help of DrawTarget/Embedded Graphics)
 /// - Rotations
 /// - Clearing
+///
+/// Reading back a nibble that isn't a color (8 to 15, only possible by writing
+/// to the buffer directly) gives the color of its lower 3 bits, the same as
+/// [`OctColor::from_nibble_lossy`] and the panel itself.
 pub trait OctDisplay: DrawTarget<Color = OctColor> {
+    /// Width of the buffer in pixels, before rotation
+    const WIDTH: u32 = WIDTH;
+    /// Height of the buffer in pixels, before rotation
+    const HEIGHT: u32 = HEIGHT;
+
     /// Returns the buffer
     fn buffer(&self) -> &[u8];
 
@@ -154,10 +340,107 @@ pub trait OctDisplay: DrawTarget<Color = OctColor> {
 
     /// Get the current rotation of the display
     fn rotation(&self) -> DisplayRotation;
+
+    /// Clears the buffer of the display with the chosen background color
+    fn clear_buffer(&mut self, background_color: OctColor) {
+        self.get_mut_buffer()
+            .fill(OctColor::colors_byte(background_color, background_color));
+    }
+
+    /// Iterates over the colors of a single row, using the rotated coordinates
+    fn row(&self, y: i32) -> BufferColors<'_> {
+        let size = logical_size(Self::WIDTH, Self::HEIGHT, self.rotation());
+        self.region(&Rectangle::new(Point::new(0, y), Size::new(size.width, 1)))
+    }
+
+    /// Iterates over the colors of an area in row-major order, using the rotated coordinates
+    ///
+    /// The area is clipped to the display, see [`BufferColors::area`]
+    fn region(&self, area: &Rectangle) -> BufferColors<'_> {
+        BufferColors::new(
+            self.buffer(),
+            Self::WIDTH,
+            Self::HEIGHT,
+            self.rotation(),
+            area,
+        )
+    }
+
+    /// Swaps the colors of everything drawn so far
+    fn recolor(&mut self, map: &ColorMap) {
+        recolor_helper(self.get_mut_buffer(), map);
+    }
+
+    /// Swaps the colors inside an area, using the rotated coordinates
+    fn recolor_region(&mut self, area: &Rectangle, map: &ColorMap) {
+        let rotation = self.rotation();
+        recolor_region_helper(
+            self.get_mut_buffer(),
+            Self::WIDTH,
+            Self::HEIGHT,
+            rotation,
+            area,
+            map,
+        );
+    }
+}
+
+/// Helperfunction for the Embedded Graphics draw trait
+pub(super) fn draw_helper(
+    buffer: &mut [u8],
+    width: u32,
+    height: u32,
+    rotation: DisplayRotation,
+    pixel: Pixel<OctColor>,
+) -> Result<(), core::convert::Infallible> {
+    let Pixel(point, color) = pixel;
+    if outside_display(point, width, height, rotation) {
+        return Ok(());
+    }
+
+    // Give us index inside the buffer and the bit-position in that u8 which needs to be changed
+    let (index, upper) = find_oct_position(point.x as u32, point.y as u32, width, height, rotation);
+    let index = index as usize;
+
+    // "Draw" the Pixel on that bit
+    let (mask, color_nibble) = if upper {
+        (0x0f, color.get_nibble() << 4)
+    } else {
+        (0xf0, color.get_nibble())
+    };
+
+    match buffer.get_mut(index) {
+        None => {
+            #[cfg(feature = "defmt")]
+            defmt::warn!(
+                "index out of buffer, {} - point ({}, {})",
+                index,
+                point.x,
+                point.y
+            );
+        }
+        Some(i) => {
+            *i = (*i & mask) | color_nibble;
+        }
+    }
+    Ok(())
+}
+
+// Copies a row of a full size buffer, which is already in the panel's format
+fn copy_panel_row(buffer: &[u8], y: u32, row: &mut [u8]) {
+    let start = y as usize * WIDTH as usize / 2;
+    if let Some(src) = buffer.get(start..start + row.len()) {
+        row.copy_from_slice(src);
+    }
 }
 
 // Checks if a pos is outside the defined display
-fn outside_display(p: Point, width: u32, height: u32, rotation: DisplayRotation) -> bool {
+pub(super) fn outside_display(
+    p: Point,
+    width: u32,
+    height: u32,
+    rotation: DisplayRotation,
+) -> bool {
     if p.x < 0 || p.y < 0 {
         return true;
     }
@@ -193,7 +476,13 @@ fn find_oct_position(
     )
 }
 
-fn find_rotation(x: u32, y: u32, width: u32, height: u32, rotation: DisplayRotation) -> (u32, u32) {
+pub(super) fn find_rotation(
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    rotation: DisplayRotation,
+) -> (u32, u32) {
     let new_x;
     let new_y;
     match rotation {
@@ -216,3 +505,370 @@ fn find_rotation(x: u32, y: u32, width: u32, height: u32, rotation: DisplayRotat
     }
     (new_x, new_y)
 }
+
+// Reads back a single pixel, None if it's outside the display
+pub(super) fn pixel_helper(
+    buffer: &[u8],
+    width: u32,
+    height: u32,
+    rotation: DisplayRotation,
+    point: Point,
+) -> Option<OctColor> {
+    if outside_display(point, width, height, rotation) {
+        return None;
+    }
+    let (index, upper) = find_oct_position(point.x as u32, point.y as u32, width, height, rotation);
+    let byte = *buffer.get(index as usize)?;
+    let nibble = if upper { byte >> 4 } else { byte };
+    Some(OctColor::from_nibble_lossy(nibble))
+}
+
+// Size of the display as seen through the rotation
+pub(super) fn logical_size(width: u32, height: u32, rotation: DisplayRotation) -> Size {
+    match rotation {
+        DisplayRotation::Rotate0 | DisplayRotation::Rotate180 => Size::new(width, height),
+        DisplayRotation::Rotate90 | DisplayRotation::Rotate270 => Size::new(height, width),
+    }
+}
+
+// Writes a single color into the nibble at `nibble` (counted from the start of the buffer)
+pub(super) fn set_nibble(buffer: &mut [u8], nibble: usize, color: OctColor) {
+    if let Some(byte) = buffer.get_mut(nibble / 2) {
+        if nibble & 0x1 == 0 {
+            *byte = (*byte & 0x0f) | (color.get_nibble() << 4);
+        } else {
+            *byte = (*byte & 0xf0) | color.get_nibble();
+        }
+    }
+}
+
+// Maps every byte of the buffer through the map's byte lookup table
+fn recolor_helper(buffer: &mut [u8], map: &ColorMap) {
+    let lut = map.byte_lut();
+    for byte in buffer.iter_mut() {
+        *byte = lut[*byte as usize];
+    }
+}
+
+// Maps the colors inside a clipped area, whole bytes through the lookup table
+// and the nibbles at the edges of each row one by one
+fn recolor_region_helper(
+    buffer: &mut [u8],
+    width: u32,
+    height: u32,
+    rotation: DisplayRotation,
+    area: &Rectangle,
+    map: &ColorMap,
+) {
+    let Some((x0, x1, y0, y1)) = panel_area(width, height, rotation, area) else {
+        return;
+    };
+    let lut = map.byte_lut();
+    let map_nibble = |buffer: &mut [u8], nibble: usize| {
+        let byte = buffer[nibble / 2];
+        let color = if nibble & 0x1 == 0 { byte >> 4 } else { byte };
+        set_nibble(buffer, nibble, map.map(OctColor::from_nibble_lossy(color)));
+    };
+
+    let width = width as usize;
+    for y in y0 as usize..y1 as usize {
+        let mut start = y * width + x0 as usize;
+        let mut end = y * width + x1 as usize;
+        if start & 0x1 == 1 {
+            map_nibble(buffer, start);
+            start += 1;
+        }
+        if end & 0x1 == 1 && end > start {
+            map_nibble(buffer, end - 1);
+            end -= 1;
+        }
+        for byte in buffer[start / 2..end / 2].iter_mut() {
+            *byte = lut[*byte as usize];
+        }
+    }
+}
+
+// Returns the nibble index of the (already clipped) logical point along with
+// how far the nibble index moves for a step of +1 in x and +1 in y
+fn nibble_walk(
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    rotation: DisplayRotation,
+) -> (isize, isize, isize) {
+    let (new_x, new_y) = find_rotation(x, y, width, height, rotation);
+    let start = (new_y * width + new_x) as isize;
+    let w = width as isize;
+    let (step_x, step_y) = match rotation {
+        DisplayRotation::Rotate0 => (1, w),
+        DisplayRotation::Rotate90 => (w, -1),
+        DisplayRotation::Rotate180 => (-1, -w),
+        DisplayRotation::Rotate270 => (-w, 1),
+    };
+    (start, step_x, step_y)
+}
+
+// Clips a logical area to the display and returns its bounds on the panel as
+// (x0, x1, y0, y1), end exclusive. A rotated rectangle is still a rectangle.
+pub(super) fn panel_area(
+    width: u32,
+    height: u32,
+    rotation: DisplayRotation,
+    area: &Rectangle,
+) -> Option<(u32, u32, u32, u32)> {
+    let area = area.intersection(&Rectangle::new(
+        Point::zero(),
+        logical_size(width, height, rotation),
+    ));
+    if area.is_zero_sized() {
+        return None;
+    }
+
+    let (lx0, ly0) = (area.top_left.x as u32, area.top_left.y as u32);
+    let (lx1, ly1) = (lx0 + area.size.width, ly0 + area.size.height);
+    Some(match rotation {
+        DisplayRotation::Rotate0 => (lx0, lx1, ly0, ly1),
+        DisplayRotation::Rotate90 => (width - ly1, width - ly0, lx0, lx1),
+        DisplayRotation::Rotate180 => (width - lx1, width - lx0, height - ly1, height - ly0),
+        DisplayRotation::Rotate270 => (ly0, ly1, height - lx1, height - lx0),
+    })
+}
+
+// Fills the nibbles from `start` up to `end` with a color, writing whole bytes where possible
+pub(super) fn fill_nibbles(buffer: &mut [u8], mut start: usize, mut end: usize, color: OctColor) {
+    if start & 0x1 == 1 && end > start {
+        set_nibble(buffer, start, color);
+        start += 1;
+    }
+    if end & 0x1 == 1 && end > start {
+        set_nibble(buffer, end - 1, color);
+        end -= 1;
+    }
+    if let Some(bytes) = buffer.get_mut(start / 2..end / 2) {
+        bytes.fill(OctColor::colors_byte(color, color));
+    }
+}
+
+// Fills a clipped area with a single color, writing whole bytes wherever two
+// horizontally neighbouring panel pixels fall inside the area
+pub(super) fn fill_solid_helper(
+    buffer: &mut [u8],
+    width: u32,
+    height: u32,
+    rotation: DisplayRotation,
+    area: &Rectangle,
+    color: OctColor,
+) {
+    let Some((x0, x1, y0, y1)) = panel_area(width, height, rotation, area) else {
+        return;
+    };
+
+    let width = width as usize;
+    // Full width rows are contiguous in the buffer
+    if x0 == 0 && x1 as usize == width {
+        fill_nibbles(buffer, y0 as usize * width, y1 as usize * width, color);
+        return;
+    }
+
+    for y in y0 as usize..y1 as usize {
+        fill_nibbles(
+            buffer,
+            y * width + x0 as usize,
+            y * width + x1 as usize,
+            color,
+        );
+    }
+}
+
+// Fills an area with colors given in row-major order, skipping the colors that
+// fall outside of the display and packing neighbouring panel pixels into whole bytes
+pub(super) fn fill_contiguous_helper<I>(
+    buffer: &mut [u8],
+    width: u32,
+    height: u32,
+    rotation: DisplayRotation,
+    area: &Rectangle,
+    colors: I,
+) where
+    I: IntoIterator<Item = OctColor>,
+{
+    let drawable = area.intersection(&Rectangle::new(
+        Point::zero(),
+        logical_size(width, height, rotation),
+    ));
+    if drawable.is_zero_sized() {
+        return;
+    }
+
+    let mut colors = colors.into_iter();
+    let area_width = area.size.width as usize;
+    let skip_left = (drawable.top_left.x - area.top_left.x) as usize;
+    let span = drawable.size.width as usize;
+    let skip_right = area_width - skip_left - span;
+    let skip_top = (drawable.top_left.y - area.top_left.y) as usize;
+
+    // Drop every color in the rows above the display
+    if skip_top > 0 && colors.nth(skip_top * area_width - 1).is_none() {
+        return;
+    }
+
+    let (row_start, step_x, step_y) = nibble_walk(
+        drawable.top_left.x as u32,
+        drawable.top_left.y as u32,
+        width,
+        height,
+        rotation,
+    );
+
+    for row in 0..drawable.size.height as isize {
+        if skip_left > 0 && colors.nth(skip_left - 1).is_none() {
+            return;
+        }
+
+        let mut nibble = row_start + row * step_y;
+        let mut remaining = span;
+        while remaining > 0 {
+            let Some(first) = colors.next() else {
+                return;
+            };
+            // Two pixels sharing a byte can be written in one go
+            let aligned = match step_x {
+                1 => nibble & 0x1 == 0,
+                -1 => nibble & 0x1 == 1,
+                _ => false,
+            };
+            if aligned && remaining >= 2 {
+                let Some(second) = colors.next() else {
+                    set_nibble(buffer, nibble as usize, first);
+                    return;
+                };
+                let byte = if step_x == 1 {
+                    OctColor::colors_byte(first, second)
+                } else {
+                    OctColor::colors_byte(second, first)
+                };
+                if let Some(b) = buffer.get_mut(nibble as usize / 2) {
+                    *b = byte;
+                }
+                nibble += 2 * step_x;
+                remaining -= 2;
+            } else {
+                set_nibble(buffer, nibble as usize, first);
+                nibble += step_x;
+                remaining -= 1;
+            }
+        }
+
+        if skip_right > 0 && colors.nth(skip_right - 1).is_none() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROTATIONS: [DisplayRotation; 4] = [
+        DisplayRotation::Rotate0,
+        DisplayRotation::Rotate90,
+        DisplayRotation::Rotate180,
+        DisplayRotation::Rotate270,
+    ];
+
+    fn areas(rotation: DisplayRotation) -> [Rectangle; 7] {
+        let size = logical_size(WIDTH, HEIGHT, rotation);
+        let (w, h) = (size.width as i32, size.height as i32);
+        [
+            Rectangle::new(Point::new(0, 0), Size::new(1, 1)),
+            Rectangle::new(Point::new(1, 1), Size::new(5, 3)),
+            Rectangle::new(Point::new(2, 7), Size::new(8, 4)),
+            Rectangle::new(Point::new(-3, -2), Size::new(7, 5)),
+            Rectangle::new(Point::new(w - 3, h - 4), Size::new(10, 9)),
+            Rectangle::new(Point::new(-1, 100), Size::new(w as u32 + 2, 3)),
+            Rectangle::new(Point::new(0, 0), size),
+        ]
+    }
+
+    fn color(i: usize) -> OctColor {
+        OctColor::from_nibble_lossy(i as u8 % 7)
+    }
+
+    #[test]
+    fn fill_solid_matches_draw_iter() {
+        let mut fast = InkyFrameDisplay::default();
+        let mut slow = InkyFrameDisplay::default();
+        for rotation in ROTATIONS {
+            fast.set_rotation(rotation);
+            slow.set_rotation(rotation);
+            for (i, area) in areas(rotation).iter().enumerate() {
+                fast.fill_solid(area, color(i)).unwrap();
+                slow.draw_iter(area.points().map(|p| Pixel(p, color(i))))
+                    .unwrap();
+                assert!(fast.buffer() == slow.buffer(), "{:?}", area);
+            }
+        }
+    }
+
+    #[test]
+    fn fill_contiguous_matches_draw_iter() {
+        let mut fast = InkyFrameDisplay::default();
+        let mut slow = InkyFrameDisplay::default();
+        for rotation in ROTATIONS {
+            fast.set_rotation(rotation);
+            slow.set_rotation(rotation);
+            for area in areas(rotation) {
+                fast.fill_contiguous(&area, (0..).map(color)).unwrap();
+                slow.draw_iter(
+                    area.points()
+                        .zip((0..).map(color))
+                        .map(|(p, c)| Pixel(p, c)),
+                )
+                .unwrap();
+                assert!(fast.buffer() == slow.buffer(), "{:?}", area);
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_nibbles_read_back_lossy() {
+        let mut display = InkyFrameDisplay::default();
+        display.set_rotation(DisplayRotation::Rotate0);
+        display.get_mut_buffer()[0] = 0xB9;
+        assert_eq!(display.pixel(Point::new(0, 0)), Some(OctColor::Blue));
+        assert_eq!(display.pixel(Point::new(1, 0)), Some(OctColor::White));
+        let mut row = display.row(0);
+        assert_eq!(row.next(), Some(OctColor::Blue));
+        assert_eq!(row.next(), Some(OctColor::White));
+        assert_eq!(display.pixel(Point::new(-1, 0)), None);
+    }
+
+    #[test]
+    fn ref_rejects_wrong_lengths() {
+        let mut short = [0u8; BUFFER_SIZE - 1];
+        assert_eq!(
+            InkyFrameDisplayRef::new(&mut short).err(),
+            Some(InvalidBufferLengthError(BUFFER_SIZE - 1))
+        );
+        let mut long = [0u8; BUFFER_SIZE + 1];
+        assert_eq!(
+            InkyFrameDisplayRef::new_cleared(&mut long, OctColor::White).err(),
+            Some(InvalidBufferLengthError(BUFFER_SIZE + 1))
+        );
+        assert!(long.iter().all(|&b| b == 0));
+        let mut exact = [0u8; BUFFER_SIZE];
+        assert!(InkyFrameDisplayRef::new(&mut exact).is_ok());
+    }
+
+    #[test]
+    fn size_follows_rotation() {
+        let mut display = InkyFrameDisplay::default();
+        display.set_rotation(DisplayRotation::Rotate0);
+        assert_eq!(display.size(), Size::new(WIDTH, HEIGHT));
+        display.set_rotation(DisplayRotation::Rotate90);
+        assert_eq!(display.size(), Size::new(HEIGHT, WIDTH));
+        display.set_rotation(DisplayRotation::Rotate270);
+        assert_eq!(display.size(), Size::new(HEIGHT, WIDTH));
+    }
+}
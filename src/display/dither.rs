@@ -0,0 +1,676 @@
+//! Dithering of full color content down to the panel's colors
+//!
+//! Snapping every pixel to its nearest [`OctColor`] posterises photos and
+//! gradients. Error diffusion ([`DitheringDrawTarget`]) spreads the difference
+//! between the wanted and the available color over the neighbouring pixels,
+//! ordered dithering ([`OrderedDitherDrawTarget`]) mixes two colors and picks
+//! one of them with a threshold that only depends on the pixel's position.
+//! Grayscale content can be ordered dithered into a single ink on white with
+//! [`GrayscaleDrawTarget`].
+use core::marker::PhantomData;
+
+use embedded_graphics::{
+    pixelcolor::{GrayColor, Rgb888},
+    prelude::*,
+    primitives::Rectangle,
+};
+
+use super::{
+    color::{rgb888, OctColor, Palette},
+    WIDTH,
+};
+
+/// Error diffusion kernels
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorDiffusion {
+    /// Floyd–Steinberg, spreads the error over the next pixel and the row below
+    FloydSteinberg,
+    /// Atkinson, only spreads 6/8 of the error which gives more contrast
+    Atkinson,
+    /// Sierra (three row), smoother than Floyd–Steinberg
+    Sierra,
+}
+
+impl ErrorDiffusion {
+    /// Offsets (x, y) and weights of the pixels the error is spread to, and the divisor
+    fn kernel(self) -> (&'static [(i32, usize, i16)], i16) {
+        match self {
+            ErrorDiffusion::FloydSteinberg => (&[(1, 0, 7), (-1, 1, 3), (0, 1, 5), (1, 1, 1)], 16),
+            ErrorDiffusion::Atkinson => (
+                &[
+                    (1, 0, 1),
+                    (2, 0, 1),
+                    (-1, 1, 1),
+                    (0, 1, 1),
+                    (1, 1, 1),
+                    (0, 2, 1),
+                ],
+                8,
+            ),
+            ErrorDiffusion::Sierra => (
+                &[
+                    (1, 0, 5),
+                    (2, 0, 3),
+                    (-2, 1, 2),
+                    (-1, 1, 4),
+                    (0, 1, 5),
+                    (1, 1, 4),
+                    (2, 1, 2),
+                    (-1, 2, 2),
+                    (0, 2, 3),
+                    (1, 2, 2),
+                ],
+                32,
+            ),
+        }
+    }
+}
+
+/// Rows of error the kernels reach: the current one and two below it
+const ERROR_ROWS: usize = 3;
+
+/// Draw target adapter that error diffuses [`Rgb888`] content onto a display
+///
+/// Error diffusion needs the pixels in raster order (left to right, top to
+/// bottom), which is how images are drawn. Jumping back up to an earlier row
+/// starts over with no error. Only the error of the current and the next two
+/// rows is kept, so `W` (the widest row that gets dithered, the panel's width
+/// by default) sets the memory used. Pixels outside of `0..W` are drawn
+/// without dithering.
+pub struct DitheringDrawTarget<'a, D, const W: usize = { WIDTH as usize }> {
+    target: &'a mut D,
+    state: DiffusionState<W>,
+}
+
+impl<'a, D, const W: usize> DitheringDrawTarget<'a, D, W>
+where
+    D: DrawTarget<Color = OctColor>,
+{
+    /// The error is measured against the palette's RGB values, so with
+    /// [`Palette::DESATURATED`] the dithering makes up for the dull inks
+    pub fn new(target: &'a mut D, diffusion: ErrorDiffusion, palette: Palette) -> Self {
+        DitheringDrawTarget {
+            target,
+            state: DiffusionState {
+                diffusion,
+                palette,
+                errors: [[[0; 3]; W]; ERROR_ROWS],
+                row: None,
+            },
+        }
+    }
+
+    /// Forgets the accumulated error, e.g. before drawing an unrelated image
+    pub fn reset(&mut self) {
+        self.state.errors = [[[0; 3]; W]; ERROR_ROWS];
+        self.state.row = None;
+    }
+}
+
+impl<D, const W: usize> Dimensions for DitheringDrawTarget<'_, D, W>
+where
+    D: DrawTarget<Color = OctColor>,
+{
+    fn bounding_box(&self) -> Rectangle {
+        self.target.bounding_box()
+    }
+}
+
+impl<D, const W: usize> DrawTarget for DitheringDrawTarget<'_, D, W>
+where
+    D: DrawTarget<Color = OctColor>,
+{
+    type Color = Rgb888;
+    type Error = D::Error;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        let state = &mut self.state;
+        self.target.draw_iter(
+            pixels
+                .into_iter()
+                .map(|Pixel(point, color)| Pixel(point, state.dither(point, color))),
+        )
+    }
+}
+
+/// Error of the rows that are being dithered
+struct DiffusionState<const W: usize> {
+    diffusion: ErrorDiffusion,
+    palette: Palette,
+    errors: [[[i16; 3]; W]; ERROR_ROWS],
+    /// Row the first entry of `errors` belongs to
+    row: Option<i32>,
+}
+
+impl<const W: usize> DiffusionState<W> {
+    /// Moves the error rows along so that the first one belongs to row `y`
+    fn advance_to(&mut self, y: i32) {
+        match self.row {
+            Some(row) if row == y => return,
+            Some(row) if y > row && ((y - row) as usize) < ERROR_ROWS => {
+                let steps = (y - row) as usize;
+                self.errors.rotate_left(steps);
+                for errors in self.errors[ERROR_ROWS - steps..].iter_mut() {
+                    *errors = [[0; 3]; W];
+                }
+            }
+            _ => self.errors = [[[0; 3]; W]; ERROR_ROWS],
+        }
+        self.row = Some(y);
+    }
+
+    /// Quantizes a single pixel, spreading its error over the following ones
+    fn dither(&mut self, point: Point, color: Rgb888) -> OctColor {
+        if point.x < 0 || point.x as usize >= W {
+            return self.palette.quantize(color);
+        }
+        self.advance_to(point.y);
+
+        let x = point.x as usize;
+        let error = self.errors[0][x];
+        let wanted = [
+            (i16::from(color.r()) + error[0]).clamp(0, 255),
+            (i16::from(color.g()) + error[1]).clamp(0, 255),
+            (i16::from(color.b()) + error[2]).clamp(0, 255),
+        ];
+        let quantized = self
+            .palette
+            .nearest(wanted[0] as u8, wanted[1] as u8, wanted[2] as u8);
+        let (r, g, b) = self.palette.rgb(quantized);
+        let diff = [
+            wanted[0] - i16::from(r),
+            wanted[1] - i16::from(g),
+            wanted[2] - i16::from(b),
+        ];
+
+        let (kernel, divisor) = self.diffusion.kernel();
+        for &(dx, dy, weight) in kernel {
+            let nx = point.x + dx;
+            if nx < 0 || nx as usize >= W {
+                continue;
+            }
+            let target = &mut self.errors[dy][nx as usize];
+            for (channel, diff) in target.iter_mut().zip(diff) {
+                *channel += diff * weight / divisor;
+            }
+        }
+        quantized
+    }
+}
+
+/// Threshold maps for ordered dithering
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OrderedDither {
+    /// 2x2 Bayer matrix, coarse and very visible
+    Bayer2,
+    /// 4x4 Bayer matrix
+    Bayer4,
+    /// 8x8 Bayer matrix, the finest of the regular patterns
+    Bayer8,
+    /// 16x16 blue noise tile, no visible pattern but a bit grainier
+    BlueNoise,
+}
+
+const BAYER2: [[u8; 2]; 2] = [[0, 2], [3, 1]];
+
+pub(super) const BAYER4: [[u8; 4]; 4] =
+    [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+const BAYER8: [[u8; 8]; 8] = [
+    [0, 32, 8, 40, 2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44, 4, 36, 14, 46, 6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [3, 35, 11, 43, 1, 33, 9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47, 7, 39, 13, 45, 5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+/// Tileable blue noise, generated with the void-and-cluster method
+const BLUE_NOISE: [[u8; 16]; 16] = [
+    [
+        234, 50, 188, 19, 58, 171, 121, 47, 163, 1, 247, 104, 22, 132, 14, 65,
+    ],
+    [
+        209, 8, 118, 97, 240, 205, 23, 228, 138, 64, 123, 170, 72, 224, 99, 149,
+    ],
+    [
+        85, 139, 229, 165, 78, 146, 111, 84, 176, 216, 30, 231, 153, 201, 42, 180,
+    ],
+    [
+        25, 62, 195, 29, 43, 185, 7, 249, 41, 100, 191, 48, 87, 5, 128, 243,
+    ],
+    [
+        221, 152, 101, 253, 130, 220, 59, 200, 156, 12, 136, 112, 255, 174, 69, 109,
+    ],
+    [
+        46, 189, 0, 73, 172, 90, 142, 116, 80, 237, 210, 61, 147, 33, 206, 160,
+    ],
+    [
+        81, 124, 217, 113, 208, 15, 241, 27, 168, 45, 178, 20, 193, 96, 225, 18,
+    ],
+    [
+        242, 164, 60, 35, 157, 53, 181, 68, 223, 105, 125, 83, 236, 131, 55, 141,
+    ],
+    [
+        197, 10, 227, 134, 246, 95, 126, 198, 148, 3, 244, 161, 71, 9, 182, 106,
+    ],
+    [
+        40, 93, 179, 75, 192, 6, 218, 36, 91, 57, 202, 34, 215, 155, 233, 74,
+    ],
+    [
+        252, 120, 150, 24, 110, 63, 166, 119, 232, 183, 133, 103, 49, 117, 31, 167,
+    ],
+    [
+        16, 212, 51, 238, 207, 137, 254, 21, 76, 151, 13, 250, 190, 88, 203, 135,
+    ],
+    [
+        102, 184, 82, 169, 38, 89, 187, 52, 204, 98, 173, 67, 129, 4, 222, 56,
+    ],
+    [
+        230, 144, 2, 127, 226, 11, 154, 114, 239, 39, 219, 28, 235, 145, 175, 77,
+    ],
+    [
+        196, 37, 248, 70, 107, 199, 66, 177, 17, 143, 115, 159, 86, 44, 108, 26,
+    ],
+    [
+        122, 92, 158, 214, 140, 32, 245, 94, 213, 79, 194, 54, 211, 186, 251, 162,
+    ],
+];
+
+impl OrderedDither {
+    /// The pixel's rank in the threshold map and the number of ranks
+    fn rank(self, x: i32, y: i32) -> (i32, i32) {
+        let (rank, levels) = match self {
+            OrderedDither::Bayer2 => (
+                BAYER2[y.rem_euclid(2) as usize][x.rem_euclid(2) as usize],
+                4,
+            ),
+            OrderedDither::Bayer4 => (
+                BAYER4[y.rem_euclid(4) as usize][x.rem_euclid(4) as usize],
+                16,
+            ),
+            OrderedDither::Bayer8 => (
+                BAYER8[y.rem_euclid(8) as usize][x.rem_euclid(8) as usize],
+                64,
+            ),
+            OrderedDither::BlueNoise => (
+                BLUE_NOISE[y.rem_euclid(16) as usize][x.rem_euclid(16) as usize],
+                256,
+            ),
+        };
+        (i32::from(rank), levels)
+    }
+
+    /// Threshold for the pixel at (x, y), the centre of its rank's slice of
+    /// 0..512, so never 0 nor 512
+    fn level(self, x: i32, y: i32) -> i32 {
+        let (rank, levels) = self.rank(x, y);
+        (rank * 2 + 1) * 256 / levels
+    }
+
+    /// Converts a color to the panel's colors based on nothing but its position
+    ///
+    /// The color is mixed from the pair of candidates that gets closest to it,
+    /// so the candidates themselves always come out unchanged. Works for any
+    /// [`RgbColor`], so it can also be used to convert pixels by hand, e.g.
+    /// when building a buffer outside of embedded-graphics.
+    pub fn dither<C: RgbColor>(self, point: Point, color: C, palette: &Palette) -> OctColor {
+        let (r, g, b) = rgb888(color);
+        match mix(palette, r, g, b) {
+            Some((_, second, amount)) if amount * 2 > self.level(point.x, point.y) => second,
+            Some((first, _, _)) => first,
+            None => palette.nearest(r, g, b),
+        }
+    }
+}
+
+/// The pair of candidates that mixes closest to the color, and how much of
+/// the second one (0..=256) goes into that mix
+///
+/// Ties go to the pair that is closer together.
+///
+/// `None` with fewer than two distinct candidates. Mixing is measured on the
+/// palette's RGB values whatever its [`ColorDistance`](super::color::ColorDistance).
+fn mix(palette: &Palette, r: u8, g: u8, b: u8) -> Option<(OctColor, OctColor, i32)> {
+    let color = [i32::from(r), i32::from(g), i32::from(b)];
+    let rgb = |c: OctColor| {
+        let (r, g, b) = palette.rgb(c);
+        [i32::from(r), i32::from(g), i32::from(b)]
+    };
+    if let Some(exact) = palette.candidates().iter().find(|c| rgb(*c) == color) {
+        return Some((exact, exact, 0));
+    }
+
+    let mut best = None;
+    let mut best_key = (i32::MAX, i32::MAX);
+    for (i, a) in palette.candidates().iter().enumerate() {
+        for b in palette.candidates().iter().skip(i + 1) {
+            let (from, to) = (rgb(a), rgb(b));
+            let step: [i32; 3] = core::array::from_fn(|i| to[i] - from[i]);
+            let length = step.iter().map(|d| d * d).sum::<i32>();
+            if length == 0 {
+                continue;
+            }
+            let along = (0..3).map(|i| (color[i] - from[i]) * step[i]).sum::<i32>();
+            let amount = (along.clamp(0, length) as i64 * 256 / length as i64) as i32;
+            let distance = ((0..3)
+                .map(|i| i64::from((color[i] - from[i]) * 256 - step[i] * amount).pow(2))
+                .sum::<i64>()
+                >> 16) as i32;
+            // Patterns of inks that differ in hue stand out more than ones
+            // that differ in lightness, e.g. blue and yellow also average to
+            // gray but look nothing like it
+            let hue_change = step.iter().max().unwrap_or(&0) - step.iter().min().unwrap_or(&0);
+            let key = (distance + hue_change * hue_change / 64, length);
+            if key < best_key {
+                best = Some((a, b, amount));
+                best_key = key;
+            }
+        }
+    }
+    best
+}
+
+/// Draw target adapter that ordered dithers any [`RgbColor`] onto a display
+///
+/// Each pixel only depends on its position, so unlike [`DitheringDrawTarget`]
+/// the pixels can be drawn in any order, which is what embedded-graphics'
+/// primitives do.
+pub struct OrderedDitherDrawTarget<'a, D, C = Rgb888> {
+    target: &'a mut D,
+    mode: OrderedDither,
+    palette: Palette,
+    _color: PhantomData<C>,
+}
+
+impl<'a, D, C> OrderedDitherDrawTarget<'a, D, C>
+where
+    D: DrawTarget<Color = OctColor>,
+    C: RgbColor,
+{
+    pub fn new(target: &'a mut D, mode: OrderedDither, palette: Palette) -> Self {
+        OrderedDitherDrawTarget {
+            target,
+            mode,
+            palette,
+            _color: PhantomData,
+        }
+    }
+}
+
+impl<D, C> Dimensions for OrderedDitherDrawTarget<'_, D, C>
+where
+    D: DrawTarget<Color = OctColor>,
+{
+    fn bounding_box(&self) -> Rectangle {
+        self.target.bounding_box()
+    }
+}
+
+impl<D, C> DrawTarget for OrderedDitherDrawTarget<'_, D, C>
+where
+    D: DrawTarget<Color = OctColor>,
+    C: RgbColor,
+{
+    type Color = C;
+    type Error = D::Error;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        let (mode, palette) = (self.mode, self.palette);
+        self.target.draw_iter(
+            pixels
+                .into_iter()
+                .map(|Pixel(point, color)| Pixel(point, mode.dither(point, color, &palette))),
+        )
+    }
+}
+
+/// Draw target adapter for grayscale content ([`Gray2`], [`Gray4`], [`Gray8`])
+///
+/// Shades of gray are drawn as a single ink on white: black for plain
+/// grayscale or any other ink for tinted monochrome. With a dither mode the
+/// amount of ink follows the shade, without one every pixel is either ink or
+/// white.
+///
+/// [`Gray2`]: embedded_graphics::pixelcolor::Gray2
+/// [`Gray4`]: embedded_graphics::pixelcolor::Gray4
+/// [`Gray8`]: embedded_graphics::pixelcolor::Gray8
+pub struct GrayscaleDrawTarget<'a, D, C> {
+    target: &'a mut D,
+    ink: OctColor,
+    mode: Option<OrderedDither>,
+    _color: PhantomData<C>,
+}
+
+impl<'a, D, C> GrayscaleDrawTarget<'a, D, C>
+where
+    D: DrawTarget<Color = OctColor>,
+    C: GrayColor,
+{
+    pub fn new(target: &'a mut D, ink: OctColor, mode: Option<OrderedDither>) -> Self {
+        GrayscaleDrawTarget {
+            target,
+            ink,
+            mode,
+            _color: PhantomData,
+        }
+    }
+
+    /// Converts a shade to ink or white based on its position
+    ///
+    /// Full black is always ink and full white always white, whatever the mode.
+    fn convert(ink: OctColor, mode: Option<OrderedDither>, point: Point, color: C) -> OctColor {
+        let max = i32::from(C::WHITE.luma());
+        // 0..=512, so full black is above every level and full white below
+        let darkness = (max - i32::from(color.luma())) * 512 / max;
+        let level = mode.map(|mode| mode.level(point.x, point.y)).unwrap_or(256);
+        if darkness > level {
+            ink
+        } else {
+            OctColor::White
+        }
+    }
+}
+
+impl<D, C> Dimensions for GrayscaleDrawTarget<'_, D, C>
+where
+    D: DrawTarget<Color = OctColor>,
+{
+    fn bounding_box(&self) -> Rectangle {
+        self.target.bounding_box()
+    }
+}
+
+impl<D, C> DrawTarget for GrayscaleDrawTarget<'_, D, C>
+where
+    D: DrawTarget<Color = OctColor>,
+    C: GrayColor,
+{
+    type Color = C;
+    type Error = D::Error;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        let (ink, mode) = (self.ink, self.mode);
+        self.target.draw_iter(
+            pixels
+                .into_iter()
+                .map(|Pixel(point, color)| Pixel(point, Self::convert(ink, mode, point, color))),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::color::{ColorDistance, ColorSet};
+    use crate::display::{DisplayRotation, InkyFrameDisplay};
+    use embedded_graphics::{
+        image::GetPixel,
+        pixelcolor::{Gray2, Gray4, Gray8},
+        primitives::PrimitiveStyle,
+    };
+
+    const MODES: [OrderedDither; 4] = [
+        OrderedDither::Bayer2,
+        OrderedDither::Bayer4,
+        OrderedDither::Bayer8,
+        OrderedDither::BlueNoise,
+    ];
+
+    #[test]
+    fn solid_inks_dither_to_themselves() {
+        let palettes = [
+            Palette::SATURATED,
+            Palette::SATURATED.with_distance(ColorDistance::OkLab),
+            Palette::DESATURATED,
+            Palette::blended(128),
+        ];
+        for palette in palettes {
+            for ink in ColorSet::INKS.iter() {
+                let (r, g, b) = palette.rgb(ink);
+                for mode in MODES {
+                    for point in Rectangle::new(Point::zero(), Size::new(16, 16)).points() {
+                        let color = Rgb888::new(r, g, b);
+                        assert_eq!(mode.dither(point, color, &palette), ink, "{mode:?} {point}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn solid_ink_rectangles_stay_solid() {
+        let area = Rectangle::new(Point::new(-3, 5), Size::new(16, 16));
+        for palette in [Palette::SATURATED, Palette::DESATURATED] {
+            for ink in ColorSet::INKS.iter() {
+                let (r, g, b) = palette.rgb(ink);
+                for mode in MODES {
+                    let mut display = InkyFrameDisplay::default();
+                    let mut target = OrderedDitherDrawTarget::new(&mut display, mode, palette);
+                    area.into_styled(PrimitiveStyle::with_fill(Rgb888::new(r, g, b)))
+                        .draw(&mut target)
+                        .unwrap();
+                    for point in area.points().filter(|p| p.x >= 0) {
+                        assert_eq!(display.pixel(point), Some(ink), "{mode:?} {point}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn mixes_follow_the_shade() {
+        let palette = Palette::SATURATED;
+        for mode in MODES {
+            let (_, levels) = mode.rank(0, 0);
+            let side = (levels as f32).sqrt() as u32;
+            let tile = Rectangle::new(Point::zero(), Size::new(side, side));
+            let black = |shade: u8| {
+                tile.points()
+                    .filter(|&p| {
+                        mode.dither(p, Rgb888::new(shade, shade, shade), &palette)
+                            == OctColor::Black
+                    })
+                    .count()
+            };
+            assert_eq!(black(0), levels as usize);
+            assert_eq!(black(128), levels as usize / 2, "{mode:?}");
+            assert_eq!(black(255), 0);
+        }
+    }
+
+    fn gray_extremes<C: GrayColor>() {
+        let area = Rectangle::new(Point::new(-3, 5), Size::new(16, 16));
+        for mode in MODES.map(Some).into_iter().chain([None]) {
+            for (shade, expected) in [(C::BLACK, OctColor::Red), (C::WHITE, OctColor::White)] {
+                let mut display = InkyFrameDisplay::default();
+                let mut target = GrayscaleDrawTarget::new(&mut display, OctColor::Red, mode);
+                area.into_styled(PrimitiveStyle::with_fill(shade))
+                    .draw(&mut target)
+                    .unwrap();
+                for point in area.points().filter(|p| p.x >= 0) {
+                    assert_eq!(display.pixel(point), Some(expected), "{mode:?} {point}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn black_and_white_stay_ink_and_white() {
+        gray_extremes::<Gray2>();
+        gray_extremes::<Gray4>();
+        gray_extremes::<Gray8>();
+    }
+
+    const DIFFUSIONS: [ErrorDiffusion; 3] = [
+        ErrorDiffusion::FloydSteinberg,
+        ErrorDiffusion::Atkinson,
+        ErrorDiffusion::Sierra,
+    ];
+
+    /// Error diffuses a 64x64 square of `color` onto the display
+    fn diffuse(diffusion: ErrorDiffusion, palette: Palette, color: Rgb888) -> InkyFrameDisplay {
+        let mut display = InkyFrameDisplay::default();
+        display.set_rotation(DisplayRotation::Rotate0);
+        let mut target = DitheringDrawTarget::<_, 64>::new(&mut display, diffusion, palette);
+        Rectangle::new(Point::zero(), Size::new(64, 64))
+            .into_styled(PrimitiveStyle::with_fill(color))
+            .draw(&mut target)
+            .unwrap();
+        display
+    }
+
+    fn count(display: &InkyFrameDisplay, color: OctColor) -> usize {
+        Rectangle::new(Point::zero(), Size::new(64, 64))
+            .points()
+            .filter(|&p| display.pixel(p) == Some(color))
+            .count()
+    }
+
+    #[test]
+    fn solid_inks_diffuse_to_themselves() {
+        for palette in [Palette::SATURATED, Palette::DESATURATED] {
+            for ink in ColorSet::INKS.iter() {
+                let (r, g, b) = palette.rgb(ink);
+                for diffusion in DIFFUSIONS {
+                    let display = diffuse(diffusion, palette, Rgb888::new(r, g, b));
+                    assert_eq!(count(&display, ink), 64 * 64, "{diffusion:?} {ink:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn diffused_grays_keep_their_shade() {
+        let palette = Palette::SATURATED.with_candidates(ColorSet::BLACK_WHITE);
+        for diffusion in DIFFUSIONS {
+            for shade in [64u8, 128, 192] {
+                let display = diffuse(diffusion, palette, Rgb888::new(shade, shade, shade));
+                let black = count(&display, OctColor::Black);
+                assert_eq!(black + count(&display, OctColor::White), 64 * 64);
+                let expected = 64 * 64 * (255 - usize::from(shade)) / 255;
+                // Atkinson drops a quarter of the error, pushing shades
+                // away from the middle
+                let tolerance = match (diffusion, shade) {
+                    (ErrorDiffusion::Atkinson, 64 | 192) => 64 * 64 / 8,
+                    _ => 64 * 64 / 32,
+                };
+                assert!(
+                    black.abs_diff(expected) <= tolerance,
+                    "{diffusion:?} {shade}: {black} black pixels, expected about {expected}"
+                );
+            }
+        }
+    }
+}
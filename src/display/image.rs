@@ -0,0 +1,308 @@
+//! Validated raw images in the panel's format
+//!
+//! `ImageRaw<OctColor>` can't report nibbles that aren't a color (8 to 15).
+//! [`OctImage`] checks them up front, either when it's created at runtime or
+//! at build time for assets pulled in with `include_bytes!`:
+//!
+//! ```ignore
+//! use inky_frame_rs::display::image::OctImage;
+//!
+//! // fails to compile if the asset contains a nibble that isn't a color
+//! const LOGO: OctImage = match OctImage::new(include_bytes!("logo.raw"), 64) {
+//!     Ok(image) => image,
+//!     Err(_) => panic!("logo.raw isn't a valid image"),
+//! };
+//! ```
+use embedded_graphics::{image::ImageDrawable, prelude::*, primitives::Rectangle};
+
+use super::color::OctColor;
+
+/// When the data of an [`OctImage`] can't be used
+#[derive(Debug, PartialEq, Eq)]
+pub enum InvalidImageError {
+    /// The width is zero or the data isn't a whole number of rows
+    Length(usize),
+    /// The pixel at this index (row-major) isn't a color
+    Color { pixel: usize, nibble: u8 },
+}
+
+impl core::fmt::Display for InvalidImageError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            InvalidImageError::Length(len) => {
+                write!(
+                    f,
+                    "Image data of {} bytes isn't a whole number of rows",
+                    len
+                )
+            }
+            InvalidImageError::Color { pixel, nibble } => {
+                write!(
+                    f,
+                    "Pixel {} is outside of possible Color Range: {}",
+                    pixel, nibble
+                )
+            }
+        }
+    }
+}
+
+/// Checks raw image data, two pixels per byte with the first in the upper
+/// nibble and every row starting on a new byte, the same as `ImageRaw<OctColor>`
+///
+/// As a `const fn` it can check assets at build time.
+pub const fn validate_image(data: &[u8], width: u32) -> Result<(), InvalidImageError> {
+    if let Err(error) = image_height(data, width) {
+        return Err(error);
+    }
+    let row_bytes = (width as usize).div_ceil(2);
+
+    let mut i = 0;
+    while i < data.len() {
+        let x = (i % row_bytes) * 2;
+        let pixel = (i / row_bytes) * width as usize + x;
+        let upper = data[i] >> 4;
+        if upper > 0x7 {
+            return Err(InvalidImageError::Color {
+                pixel,
+                nibble: upper,
+            });
+        }
+        // the lower nibble of an odd width row's last byte is padding
+        let lower = data[i] & 0xf;
+        if x + 1 < width as usize && lower > 0x7 {
+            return Err(InvalidImageError::Color {
+                pixel: pixel + 1,
+                nibble: lower,
+            });
+        }
+        i += 1;
+    }
+    Ok(())
+}
+
+// Height of the image, if the data is a whole number of rows
+const fn image_height(data: &[u8], width: u32) -> Result<u32, InvalidImageError> {
+    let row_bytes = (width as usize).div_ceil(2);
+    if width == 0 || !data.len().is_multiple_of(row_bytes) {
+        return Err(InvalidImageError::Length(data.len()));
+    }
+    Ok((data.len() / row_bytes) as u32)
+}
+
+/// Raw image of [`OctColor`]s whose nibbles are known to be colors
+///
+/// Uses the same layout as `ImageRaw<OctColor>` (see [`validate_image`]) and
+/// is drawn with `embedded_graphics::image::Image`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OctImage<'a> {
+    data: &'a [u8],
+    size: Size,
+    /// Color for nibbles that aren't a color, only used by [`OctImage::new_remapped`]
+    fallback: OctColor,
+}
+
+impl<'a> OctImage<'a> {
+    /// Creates the image, rejecting data with nibbles that aren't a color
+    pub const fn new(data: &'a [u8], width: u32) -> Result<Self, InvalidImageError> {
+        if let Err(error) = validate_image(data, width) {
+            return Err(error);
+        }
+        Self::new_remapped(data, width, OctColor::White)
+    }
+
+    /// Creates the image, drawing nibbles that aren't a color as `fallback`
+    pub const fn new_remapped(
+        data: &'a [u8],
+        width: u32,
+        fallback: OctColor,
+    ) -> Result<Self, InvalidImageError> {
+        match image_height(data, width) {
+            Ok(height) => Ok(OctImage {
+                data,
+                size: Size::new(width, height),
+                fallback,
+            }),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// The color of the pixel at (x, y), which has to be inside the image
+    fn color_at(&self, x: u32, y: u32) -> OctColor {
+        let row_bytes = (self.size.width as usize).div_ceil(2);
+        let byte = self.data[y as usize * row_bytes + x as usize / 2];
+        let nibble = if x & 0x1 == 0 { byte >> 4 } else { byte & 0xf };
+        match OctColor::from_nibble(nibble) {
+            Ok(color) => color,
+            Err(_) => self.fallback,
+        }
+    }
+}
+
+impl OriginDimensions for OctImage<'_> {
+    fn size(&self) -> Size {
+        self.size
+    }
+}
+
+impl ImageDrawable for OctImage<'_> {
+    type Color = OctColor;
+
+    fn draw<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        self.draw_sub_image(target, &self.bounding_box())
+    }
+
+    fn draw_sub_image<D>(&self, target: &mut D, area: &Rectangle) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        // Don't draw anything if `area` is zero sized or partially outside the image.
+        if area.is_zero_sized()
+            || area.top_left.x < 0
+            || area.top_left.y < 0
+            || area.top_left.x as u32 + area.size.width > self.size.width
+            || area.top_left.y as u32 + area.size.height > self.size.height
+        {
+            return Ok(());
+        }
+
+        target.fill_contiguous(
+            &Rectangle::new(Point::zero(), area.size),
+            area.points().map(|p| self.color_at(p.x as u32, p.y as u32)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::{DisplayRotation, InkyFrameDisplay};
+    use embedded_graphics::image::{GetPixel, Image, ImageDrawableExt};
+
+    // 3x2 image, black red blue / green white yellow, with padding nibbles
+    const DATA: [u8; 4] = [0x04, 0x3f, 0x21, 0x5f];
+
+    const IMAGE: OctImage = match OctImage::new(&DATA, 3) {
+        Ok(image) => image,
+        Err(_) => panic!("DATA isn't a valid image"),
+    };
+
+    fn display() -> InkyFrameDisplay {
+        let mut display = InkyFrameDisplay::default();
+        display.set_rotation(DisplayRotation::Rotate0);
+        display.clear_buffer(OctColor::Orange);
+        display
+    }
+
+    #[test]
+    fn odd_widths_ignore_the_padding() {
+        assert_eq!(validate_image(&DATA, 3), Ok(()));
+        // with an even width the same nibbles are pixels
+        assert_eq!(
+            validate_image(&DATA, 4),
+            Err(InvalidImageError::Color {
+                pixel: 3,
+                nibble: 0xf
+            })
+        );
+    }
+
+    #[test]
+    fn reports_the_bad_pixel() {
+        assert_eq!(
+            validate_image(&[0x01, 0x23, 0x45, 0x96], 4),
+            Err(InvalidImageError::Color {
+                pixel: 6,
+                nibble: 0x9
+            })
+        );
+        assert_eq!(
+            validate_image(&[0x01, 0x20, 0x4c, 0x60], 3),
+            Err(InvalidImageError::Color {
+                pixel: 4,
+                nibble: 0xc
+            })
+        );
+        assert_eq!(
+            validate_image(&[0x01, 0x20, 0xa5, 0x60], 3),
+            Err(InvalidImageError::Color {
+                pixel: 3,
+                nibble: 0xa
+            })
+        );
+    }
+
+    #[test]
+    fn lengths_are_checked() {
+        assert_eq!(
+            OctImage::new(&DATA[..3], 3),
+            Err(InvalidImageError::Length(3))
+        );
+        assert_eq!(OctImage::new(&DATA, 0), Err(InvalidImageError::Length(4)));
+        assert_eq!(IMAGE.size(), Size::new(3, 2));
+    }
+
+    #[test]
+    fn draws_the_pixels() {
+        let mut display = display();
+        Image::new(&IMAGE, Point::new(1, 1))
+            .draw(&mut display)
+            .unwrap();
+        let expected = [
+            [OctColor::Black, OctColor::Red, OctColor::Blue],
+            [OctColor::Green, OctColor::White, OctColor::Yellow],
+        ];
+        for (y, row) in expected.iter().enumerate() {
+            for (x, color) in row.iter().enumerate() {
+                let point = Point::new(x as i32 + 1, y as i32 + 1);
+                assert_eq!(display.pixel(point), Some(*color), "{:?}", point);
+            }
+        }
+        assert_eq!(display.pixel(Point::new(4, 1)), Some(OctColor::Orange));
+    }
+
+    #[test]
+    fn remapped_nibbles_use_the_fallback() {
+        let data = [0x9b, 0x12];
+        let image = OctImage::new_remapped(&data, 2, OctColor::Green).unwrap();
+        let mut display = display();
+        Image::new(&image, Point::zero())
+            .draw(&mut display)
+            .unwrap();
+        assert_eq!(display.pixel(Point::new(0, 0)), Some(OctColor::Green));
+        assert_eq!(display.pixel(Point::new(1, 0)), Some(OctColor::Green));
+        assert_eq!(display.pixel(Point::new(0, 1)), Some(OctColor::White));
+        assert_eq!(display.pixel(Point::new(1, 1)), Some(OctColor::Green));
+    }
+
+    #[test]
+    fn sub_images_are_clipped_to_the_image() {
+        let mut display = display();
+        let area = Rectangle::new(Point::new(1, 0), Size::new(2, 2));
+        Image::new(&IMAGE.sub_image(&area), Point::new(10, 10))
+            .draw(&mut display)
+            .unwrap();
+        assert_eq!(display.pixel(Point::new(10, 10)), Some(OctColor::Red));
+        assert_eq!(display.pixel(Point::new(11, 10)), Some(OctColor::Blue));
+        assert_eq!(display.pixel(Point::new(10, 11)), Some(OctColor::White));
+        assert_eq!(display.pixel(Point::new(11, 11)), Some(OctColor::Yellow));
+        assert_eq!(display.pixel(Point::new(12, 10)), Some(OctColor::Orange));
+
+        // areas reaching outside of the image aren't drawn at all
+        let orange = OctColor::colors_byte(OctColor::Orange, OctColor::Orange);
+        for area in [
+            Rectangle::new(Point::new(2, 0), Size::new(2, 1)),
+            Rectangle::new(Point::new(0, 1), Size::new(1, 2)),
+            Rectangle::new(Point::new(-1, 0), Size::new(2, 1)),
+            Rectangle::new(Point::new(0, 0), Size::zero()),
+        ] {
+            let mut target = InkyFrameDisplay::default();
+            target.clear_buffer(OctColor::Orange);
+            IMAGE.draw_sub_image(&mut target, &area).unwrap();
+            assert!(target.buffer().iter().all(|&b| b == orange), "{:?}", area);
+        }
+    }
+}
@@ -6,7 +6,7 @@ use embedded_hal::{
     digital::v2::OutputPin,
 };
 
-use super::IsBusy;
+use super::{IsBusy, Progress, RefreshHook, RefreshPhase};
 /// Interface for the display
 pub(crate) struct DisplayInterface<SPI, CS, DC, RST> {
     /// SPI
@@ -28,7 +28,7 @@ where
 {
     pub fn new(cs: CS, dc: DC, rst: RST) -> Self {
         DisplayInterface {
-            _spi: PhantomData::default(),
+            _spi: PhantomData,
             cs,
             dc,
             rst,
@@ -111,6 +111,26 @@ where
         while busy_signal.is_busy() {}
     }
 
+    /// waits until the device is not busy, calling the hook at least once and
+    /// then on every poll with the time since `start`
+    pub(crate) fn wait_until_idle_with(
+        &mut self,
+        busy_signal: &mut impl IsBusy,
+        hook: &mut impl RefreshHook,
+        phase: RefreshPhase,
+        start: u32,
+    ) -> Progress {
+        loop {
+            let elapsed = hook.now().wrapping_sub(start);
+            if hook.progress(phase, elapsed) == Progress::Abort {
+                return Progress::Abort;
+            }
+            if !busy_signal.is_busy() {
+                return Progress::Continue;
+            }
+        }
+    }
+
     /// reset the display using the reset pin
     pub(crate) fn reset(&mut self, busy_signal: &mut impl IsBusy) {
         let _ = self.rst.set_low();
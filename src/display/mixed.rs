@@ -0,0 +1,350 @@
+//! Virtual colors made up of patterns of the panel's colors
+//!
+//! Eight colors aren't much for charts and themes. A [`MixedColor`] is a 4x4
+//! pattern of [`OctColor`]s that looks like a new color from a distance, e.g.
+//! pink out of red and white. Draw with them through a [`MixedColorDrawTarget`],
+//! which picks the pattern's color for every pixel position.
+use embedded_graphics::{pixelcolor::PixelColor, prelude::*, primitives::Rectangle};
+
+use super::{color::OctColor, dither::BAYER4};
+
+/// A 4x4 pattern of colors, anchored to the display's (0, 0)
+///
+/// As the pattern only depends on the pixel position, shapes drawn next to
+/// each other with the same mix line up without seams.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct MixedColor {
+    /// Nibble of the color at (x, y) in bits `4 * (4 * y + x)`
+    pattern: u64,
+}
+
+impl PixelColor for MixedColor {
+    type Raw = ();
+}
+
+impl From<OctColor> for MixedColor {
+    fn from(color: OctColor) -> Self {
+        MixedColor::solid(color)
+    }
+}
+
+impl MixedColor {
+    /// Pink, red and white checkerboard
+    pub const PINK: MixedColor = MixedColor::checker(OctColor::Red, OctColor::White);
+    /// Gray, black and white checkerboard
+    pub const GRAY: MixedColor = MixedColor::checker(OctColor::Black, OctColor::White);
+    /// Light gray, a quarter black on white
+    pub const LIGHT_GRAY: MixedColor = MixedColor::blend(OctColor::White, OctColor::Black, 4);
+    /// Dark gray, a quarter white on black
+    pub const DARK_GRAY: MixedColor = MixedColor::blend(OctColor::Black, OctColor::White, 4);
+    /// Brown, orange and black checkerboard
+    pub const BROWN: MixedColor = MixedColor::checker(OctColor::Orange, OctColor::Black);
+    /// Dark red, red and black checkerboard
+    pub const MAROON: MixedColor = MixedColor::checker(OctColor::Red, OctColor::Black);
+    /// Purple, red and blue checkerboard
+    pub const PURPLE: MixedColor = MixedColor::checker(OctColor::Red, OctColor::Blue);
+    /// Light blue, blue and white checkerboard
+    pub const SKY_BLUE: MixedColor = MixedColor::checker(OctColor::Blue, OctColor::White);
+    /// Navy, blue and black checkerboard
+    pub const NAVY: MixedColor = MixedColor::checker(OctColor::Blue, OctColor::Black);
+    /// Teal, green and blue checkerboard
+    pub const TEAL: MixedColor = MixedColor::checker(OctColor::Green, OctColor::Blue);
+    /// Light green, green and white checkerboard
+    pub const MINT: MixedColor = MixedColor::checker(OctColor::Green, OctColor::White);
+    /// Lime, green and yellow checkerboard
+    pub const LIME: MixedColor = MixedColor::checker(OctColor::Green, OctColor::Yellow);
+    /// Olive, yellow and black checkerboard
+    pub const OLIVE: MixedColor = MixedColor::checker(OctColor::Yellow, OctColor::Black);
+    /// Cream, a quarter yellow on white
+    pub const CREAM: MixedColor = MixedColor::blend(OctColor::White, OctColor::Yellow, 4);
+    /// Peach, orange and white checkerboard
+    pub const PEACH: MixedColor = MixedColor::checker(OctColor::Orange, OctColor::White);
+    /// Amber, orange and yellow checkerboard
+    pub const AMBER: MixedColor = MixedColor::checker(OctColor::Orange, OctColor::Yellow);
+
+    /// A single color everywhere
+    pub const fn solid(color: OctColor) -> MixedColor {
+        MixedColor {
+            pattern: 0x1111_1111_1111_1111 * color as u64,
+        }
+    }
+
+    /// An even checkerboard of two colors
+    pub const fn checker(a: OctColor, b: OctColor) -> MixedColor {
+        MixedColor::from_pattern2x2([[a, b], [b, a]])
+    }
+
+    /// `amount` out of 16 pixels of `b` spread evenly over `a`
+    ///
+    /// Uses the 4x4 Bayer order, so 0 is all `a`, 8 is a checkerboard and 16
+    /// (or more) is all `b`
+    pub const fn blend(a: OctColor, b: OctColor, amount: u8) -> MixedColor {
+        let mut pattern = [[a; 4]; 4];
+        let mut y = 0;
+        while y < 4 {
+            let mut x = 0;
+            while x < 4 {
+                if BAYER4[y][x] < amount {
+                    pattern[y][x] = b;
+                }
+                x += 1;
+            }
+            y += 1;
+        }
+        MixedColor::from_pattern(pattern)
+    }
+
+    /// A 2x2 pattern, repeated to fill the 4x4 pattern
+    pub const fn from_pattern2x2(pattern: [[OctColor; 2]; 2]) -> MixedColor {
+        let mut full = [[OctColor::Black; 4]; 4];
+        let mut y = 0;
+        while y < 4 {
+            let mut x = 0;
+            while x < 4 {
+                full[y][x] = pattern[y % 2][x % 2];
+                x += 1;
+            }
+            y += 1;
+        }
+        MixedColor::from_pattern(full)
+    }
+
+    /// Any 4x4 pattern, indexed `[y][x]`
+    pub const fn from_pattern(pattern: [[OctColor; 4]; 4]) -> MixedColor {
+        let mut bits = 0u64;
+        let mut y = 0;
+        while y < 4 {
+            let mut x = 0;
+            while x < 4 {
+                bits |= (pattern[y][x] as u64) << (4 * (4 * y + x));
+                x += 1;
+            }
+            y += 1;
+        }
+        MixedColor { pattern: bits }
+    }
+
+    /// The color of the pattern at a position on the display
+    pub const fn color_at(self, point: Point) -> OctColor {
+        let x = point.x.rem_euclid(4) as u64;
+        let y = point.y.rem_euclid(4) as u64;
+        match OctColor::from_nibble(((self.pattern >> (4 * (4 * y + x))) & 0xf) as u8) {
+            Ok(color) => color,
+            Err(_) => OctColor::White,
+        }
+    }
+
+    /// The color if the pattern is a single color
+    pub const fn as_solid(self) -> Option<OctColor> {
+        let first = self.pattern & 0xf;
+        if self.pattern == 0x1111_1111_1111_1111 * first {
+            match OctColor::from_nibble(first as u8) {
+                Ok(color) => Some(color),
+                Err(_) => None,
+            }
+        } else {
+            None
+        }
+    }
+}
+
+/// Draw target adapter that resolves [`MixedColor`]s to the display's colors
+pub struct MixedColorDrawTarget<'a, D> {
+    target: &'a mut D,
+}
+
+impl<'a, D> MixedColorDrawTarget<'a, D>
+where
+    D: DrawTarget<Color = OctColor>,
+{
+    pub fn new(target: &'a mut D) -> Self {
+        MixedColorDrawTarget { target }
+    }
+}
+
+impl<D> Dimensions for MixedColorDrawTarget<'_, D>
+where
+    D: DrawTarget<Color = OctColor>,
+{
+    fn bounding_box(&self) -> Rectangle {
+        self.target.bounding_box()
+    }
+}
+
+impl<D> DrawTarget for MixedColorDrawTarget<'_, D>
+where
+    D: DrawTarget<Color = OctColor>,
+{
+    type Color = MixedColor;
+    type Error = D::Error;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        self.target.draw_iter(
+            pixels
+                .into_iter()
+                .map(|Pixel(point, color)| Pixel(point, color.color_at(point))),
+        )
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        // keep the target's fast path for plain colors
+        if let Some(solid) = color.as_solid() {
+            return self.target.fill_solid(area, solid);
+        }
+        self.target
+            .fill_contiguous(area, area.points().map(|point| color.color_at(point)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::{DisplayRotation, InkyFrameDisplay};
+    use embedded_graphics::image::GetPixel;
+
+    /// Counts how the adapter draws
+    #[derive(Default)]
+    struct Calls {
+        pixels: usize,
+        solid: usize,
+        contiguous: usize,
+    }
+
+    impl OriginDimensions for Calls {
+        fn size(&self) -> Size {
+            Size::new(16, 16)
+        }
+    }
+
+    impl DrawTarget for Calls {
+        type Color = OctColor;
+        type Error = core::convert::Infallible;
+
+        fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+        where
+            I: IntoIterator<Item = Pixel<Self::Color>>,
+        {
+            self.pixels += pixels.into_iter().count();
+            Ok(())
+        }
+
+        fn fill_contiguous<I>(&mut self, _area: &Rectangle, _colors: I) -> Result<(), Self::Error>
+        where
+            I: IntoIterator<Item = Self::Color>,
+        {
+            self.contiguous += 1;
+            Ok(())
+        }
+
+        fn fill_solid(
+            &mut self,
+            _area: &Rectangle,
+            _color: Self::Color,
+        ) -> Result<(), Self::Error> {
+            self.solid += 1;
+            Ok(())
+        }
+    }
+
+    fn count(mix: MixedColor, color: OctColor) -> usize {
+        Rectangle::new(Point::zero(), Size::new(4, 4))
+            .points()
+            .filter(|p| mix.color_at(*p) == color)
+            .count()
+    }
+
+    #[test]
+    fn checker_alternates() {
+        let mix = MixedColor::checker(OctColor::Red, OctColor::White);
+        assert_eq!(mix, MixedColor::PINK);
+        for p in Rectangle::new(Point::new(-4, -4), Size::new(8, 8)).points() {
+            let expected = if (p.x + p.y) % 2 == 0 {
+                OctColor::Red
+            } else {
+                OctColor::White
+            };
+            assert_eq!(mix.color_at(p), expected, "{:?}", p);
+        }
+    }
+
+    #[test]
+    fn blend_amounts() {
+        let (a, b) = (OctColor::White, OctColor::Black);
+        for amount in 0..=16 {
+            let mix = MixedColor::blend(a, b, amount);
+            assert_eq!(count(mix, b), usize::from(amount));
+            assert_eq!(count(mix, a), 16 - usize::from(amount));
+        }
+        assert_eq!(MixedColor::blend(a, b, 0), MixedColor::solid(a));
+        assert_eq!(MixedColor::blend(a, b, 8), MixedColor::checker(b, a));
+        assert_eq!(MixedColor::blend(a, b, 200), MixedColor::solid(b));
+        assert_eq!(count(MixedColor::LIGHT_GRAY, b), 4);
+        assert_eq!(count(MixedColor::DARK_GRAY, a), 4);
+    }
+
+    #[test]
+    fn color_at_repeats_every_four_pixels() {
+        let mut pattern = [[OctColor::Black; 4]; 4];
+        for (y, row) in pattern.iter_mut().enumerate() {
+            for (x, color) in row.iter_mut().enumerate() {
+                *color = OctColor::from_nibble_lossy((x + y) as u8 % 8);
+            }
+        }
+        let mix = MixedColor::from_pattern(pattern);
+        for p in Rectangle::new(Point::new(-9, -9), Size::new(20, 20)).points() {
+            let (x, y) = (p.x.rem_euclid(4) as usize, p.y.rem_euclid(4) as usize);
+            assert_eq!(mix.color_at(p), pattern[y][x], "{:?}", p);
+        }
+    }
+
+    #[test]
+    fn as_solid_only_for_single_colors() {
+        assert_eq!(
+            MixedColor::solid(OctColor::Blue).as_solid(),
+            Some(OctColor::Blue)
+        );
+        assert_eq!(
+            MixedColor::checker(OctColor::Green, OctColor::Green).as_solid(),
+            Some(OctColor::Green)
+        );
+        assert_eq!(
+            MixedColor::from(OctColor::HiZ).as_solid(),
+            Some(OctColor::HiZ)
+        );
+        assert_eq!(MixedColor::GRAY.as_solid(), None);
+        assert_eq!(
+            MixedColor::blend(OctColor::Red, OctColor::Yellow, 1).as_solid(),
+            None
+        );
+    }
+
+    #[test]
+    fn solid_fills_keep_the_fast_path() {
+        let area = Rectangle::new(Point::new(1, 2), Size::new(5, 3));
+        let mut calls = Calls::default();
+        let mut target = MixedColorDrawTarget::new(&mut calls);
+        target.fill_solid(&area, OctColor::Red.into()).unwrap();
+        target.fill_solid(&area, MixedColor::PURPLE).unwrap();
+        target.clear(OctColor::White.into()).unwrap();
+        assert_eq!((calls.solid, calls.contiguous, calls.pixels), (2, 1, 0));
+    }
+
+    #[test]
+    fn patterns_line_up_with_the_display() {
+        let mut display = InkyFrameDisplay::default();
+        display.set_rotation(DisplayRotation::Rotate0);
+        let mut target = MixedColorDrawTarget::new(&mut display);
+        let left = Rectangle::new(Point::new(1, 1), Size::new(3, 5));
+        let right = Rectangle::new(Point::new(4, 1), Size::new(6, 5));
+        target.fill_solid(&left, MixedColor::TEAL).unwrap();
+        target
+            .draw_iter(right.points().map(|p| Pixel(p, MixedColor::TEAL)))
+            .unwrap();
+        for p in Rectangle::new(Point::new(1, 1), Size::new(9, 5)).points() {
+            assert_eq!(display.pixel(p), Some(MixedColor::TEAL.color_at(p)));
+        }
+    }
+}
@@ -1,4 +1,3 @@
-pub mod color;
 /**
  * The display driver for the inky frame uc8159
  * A lot of code is modified from https://github.com/caemor/epd-waveshare
@@ -14,15 +13,36 @@ pub mod color;
  *
  */
 mod command;
+#[allow(clippy::module_inception)]
 mod display;
 mod interface;
+mod oklab;
+mod progress;
+mod reduced;
+mod rle;
 mod traits;
 
+pub mod adjust;
+pub mod color;
+pub mod dither;
+pub mod image;
+pub mod mixed;
+pub mod recolor;
+
 use crate::display::interface::DisplayInterface;
 use color::OctColor;
-pub use display::InkyFrameDisplay;
+pub use display::{
+    BufferColors, DisplayRotation, InkyFrameDisplay, InkyFrameDisplayRef, InvalidBufferLengthError,
+    OctDisplay,
+};
 use embedded_hal::{blocking::spi::Write, digital::v2::OutputPin};
-pub use traits::IsBusy;
+pub use progress::{NoRefreshHook, Progress, RefreshError, RefreshPhase};
+pub use reduced::{
+    HalfResolutionDisplay, InvalidPaletteLengthError, MonoPaletteDisplay, PaletteDisplay,
+    QuadPaletteDisplay,
+};
+pub use rle::{RleDisplay, RleOverflowPolicy, RowBudgetExceededError, MIN_ROW_BUDGET};
+pub use traits::{FrameSource, IsBusy, RefreshHook, TryIsBusy};
 
 use self::command::Command;
 
@@ -30,6 +50,8 @@ use self::command::Command;
 pub const WIDTH: u32 = 600;
 /// Height of the display
 pub const HEIGHT: u32 = 448;
+/// Size in bytes of a full frame buffer, two pixels per byte
+pub const BUFFER_SIZE: usize = WIDTH as usize / 2 * HEIGHT as usize;
 /// Default Background Color
 pub const DEFAULT_BACKGROUND_COLOR: OctColor = OctColor::White;
 
@@ -109,11 +131,62 @@ where
         busy_signal: &mut impl IsBusy,
         buffer: &[u8],
     ) -> Result<(), SPI::Error> {
-        self.busy_wait(busy_signal);
-        self.update_vcom(spi)?;
-        self.send_resolution(spi)?;
-        self.cmd_with_data(spi, Command::DataStartTransmission1, buffer)?;
+        without_hook(self.update_frame_with(spi, busy_signal, buffer, &mut NoRefreshHook))
+    }
+
+    /// Like [`update_frame`](Self::update_frame), calling `hook` while the
+    /// panel finishes earlier work
+    pub fn update_frame_with(
+        &mut self,
+        spi: &mut SPI,
+        busy_signal: &mut impl IsBusy,
+        buffer: &[u8],
+        hook: &mut impl RefreshHook,
+    ) -> Result<(), RefreshError<SPI::Error>> {
+        let start = hook.now();
+        self.hooked_wait(busy_signal, hook, RefreshPhase::Pending, start)?;
+        self.update_vcom(spi).map_err(RefreshError::Spi)?;
+        self.send_resolution(spi).map_err(RefreshError::Spi)?;
+        self.cmd_with_data(spi, Command::DataStartTransmission1, buffer)
+            .map_err(RefreshError::Spi)?;
         self.command(spi, Command::DataStop)
+            .map_err(RefreshError::Spi)
+    }
+
+    /// Like [update_frame](Self::update_frame) but pulls the frame row by row
+    /// from a [FrameSource], so buffers in other formats can be expanded while
+    /// they are streamed
+    pub fn update_frame_from(
+        &mut self,
+        spi: &mut SPI,
+        busy_signal: &mut impl IsBusy,
+        source: &impl FrameSource,
+    ) -> Result<(), SPI::Error> {
+        without_hook(self.update_frame_from_with(spi, busy_signal, source, &mut NoRefreshHook))
+    }
+
+    /// Like [`update_frame_from`](Self::update_frame_from), calling `hook`
+    /// while the panel finishes earlier work
+    pub fn update_frame_from_with(
+        &mut self,
+        spi: &mut SPI,
+        busy_signal: &mut impl IsBusy,
+        source: &impl FrameSource,
+        hook: &mut impl RefreshHook,
+    ) -> Result<(), RefreshError<SPI::Error>> {
+        let mut row = [0u8; WIDTH as usize / 2];
+        let start = hook.now();
+        self.hooked_wait(busy_signal, hook, RefreshPhase::Pending, start)?;
+        self.update_vcom(spi).map_err(RefreshError::Spi)?;
+        self.send_resolution(spi).map_err(RefreshError::Spi)?;
+        self.command(spi, Command::DataStartTransmission1)
+            .map_err(RefreshError::Spi)?;
+        for y in 0..HEIGHT {
+            source.panel_row(y, &mut row);
+            self.send_data(spi, &row).map_err(RefreshError::Spi)?;
+        }
+        self.command(spi, Command::DataStop)
+            .map_err(RefreshError::Spi)
     }
 
     pub fn display_frame(
@@ -121,14 +194,32 @@ where
         spi: &mut SPI,
         busy_signal: &mut impl IsBusy,
     ) -> Result<(), SPI::Error> {
-        self.busy_wait(busy_signal);
-        self.command(spi, Command::PowerOn)?;
-        self.busy_wait(busy_signal);
-        self.command(spi, Command::DisplayRefresh)?;
-        self.busy_wait(busy_signal);
-        self.command(spi, Command::PowerOff)?;
-        self.busy_wait(busy_signal);
-        Ok(())
+        without_hook(self.display_frame_with(spi, busy_signal, &mut NoRefreshHook))
+    }
+
+    /// Like [`display_frame`](Self::display_frame), calling `hook` while the
+    /// panel is busy
+    ///
+    /// When the hook aborts the panel is left as it is, it may still be
+    /// refreshing and powered on. Wait for it to go idle before sending it
+    /// anything else.
+    pub fn display_frame_with(
+        &mut self,
+        spi: &mut SPI,
+        busy_signal: &mut impl IsBusy,
+        hook: &mut impl RefreshHook,
+    ) -> Result<(), RefreshError<SPI::Error>> {
+        let start = hook.now();
+        self.hooked_wait(busy_signal, hook, RefreshPhase::Pending, start)?;
+        self.command(spi, Command::PowerOn)
+            .map_err(RefreshError::Spi)?;
+        self.hooked_wait(busy_signal, hook, RefreshPhase::PowerOn, start)?;
+        self.command(spi, Command::DisplayRefresh)
+            .map_err(RefreshError::Spi)?;
+        self.hooked_wait(busy_signal, hook, RefreshPhase::Refresh, start)?;
+        self.command(spi, Command::PowerOff)
+            .map_err(RefreshError::Spi)?;
+        self.hooked_wait(busy_signal, hook, RefreshPhase::PowerOff, start)
     }
 
     pub fn update_and_display_frame(
@@ -137,9 +228,25 @@ where
         busy_signal: &mut impl IsBusy,
         buffer: &[u8],
     ) -> Result<(), SPI::Error> {
-        self.update_frame(spi, busy_signal, buffer)?;
-        self.display_frame(spi, busy_signal)?;
-        Ok(())
+        without_hook(self.update_and_display_frame_with(
+            spi,
+            busy_signal,
+            buffer,
+            &mut NoRefreshHook,
+        ))
+    }
+
+    /// Like [`update_and_display_frame`](Self::update_and_display_frame),
+    /// calling `hook` whenever the panel is busy
+    pub fn update_and_display_frame_with(
+        &mut self,
+        spi: &mut SPI,
+        busy_signal: &mut impl IsBusy,
+        buffer: &[u8],
+        hook: &mut impl RefreshHook,
+    ) -> Result<(), RefreshError<SPI::Error>> {
+        self.update_frame_with(spi, busy_signal, buffer, hook)?;
+        self.display_frame_with(spi, busy_signal, hook)
     }
 
     pub fn clear_frame(
@@ -147,14 +254,28 @@ where
         spi: &mut SPI,
         busy_signal: &mut impl IsBusy,
     ) -> Result<(), SPI::Error> {
+        without_hook(self.clear_frame_with(spi, busy_signal, &mut NoRefreshHook))
+    }
+
+    /// Like [`clear_frame`](Self::clear_frame), calling `hook` whenever the
+    /// panel is busy
+    pub fn clear_frame_with(
+        &mut self,
+        spi: &mut SPI,
+        busy_signal: &mut impl IsBusy,
+        hook: &mut impl RefreshHook,
+    ) -> Result<(), RefreshError<SPI::Error>> {
         let bg = OctColor::colors_byte(self.color, self.color);
-        self.busy_wait(busy_signal);
-        self.update_vcom(spi)?;
-        self.send_resolution(spi)?;
-        self.command(spi, Command::DataStartTransmission1)?;
-        self.interface.data_x_times(spi, bg, WIDTH / 2 * HEIGHT)?;
-        self.display_frame(spi, busy_signal)?;
-        Ok(())
+        let start = hook.now();
+        self.hooked_wait(busy_signal, hook, RefreshPhase::Pending, start)?;
+        self.update_vcom(spi).map_err(RefreshError::Spi)?;
+        self.send_resolution(spi).map_err(RefreshError::Spi)?;
+        self.command(spi, Command::DataStartTransmission1)
+            .map_err(RefreshError::Spi)?;
+        self.interface
+            .data_x_times(spi, bg, WIDTH / 2 * HEIGHT)
+            .map_err(RefreshError::Spi)?;
+        self.display_frame_with(spi, busy_signal, hook)
     }
 
     pub fn set_background_color(&mut self, color: OctColor) {
@@ -203,7 +324,225 @@ where
         Ok(())
     }
 
-    fn busy_wait(&mut self, busy_signal: &mut impl IsBusy) {
-        self.interface.wait_until_idle(busy_signal)
+    fn hooked_wait(
+        &mut self,
+        busy_signal: &mut impl IsBusy,
+        hook: &mut impl RefreshHook,
+        phase: RefreshPhase,
+        start: u32,
+    ) -> Result<(), RefreshError<SPI::Error>> {
+        match self
+            .interface
+            .wait_until_idle_with(busy_signal, hook, phase, start)
+        {
+            Progress::Continue => Ok(()),
+            Progress::Abort => Err(RefreshError::Aborted(phase)),
+        }
+    }
+}
+
+/// The result of a call with [`NoRefreshHook`], which never aborts
+fn without_hook<E>(result: Result<(), RefreshError<E>>) -> Result<(), E> {
+    match result {
+        Err(RefreshError::Spi(error)) => Err(error),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::Cell;
+    use core::convert::Infallible;
+
+    /// A panel that stays busy for a number of polls after every command
+    /// that powers it on, refreshes or powers it off, each poll taking 100ms
+    #[derive(Default)]
+    struct Panel {
+        data: Cell<bool>,
+        commands: Cell<[u8; 32]>,
+        sent: Cell<usize>,
+        busy_polls: Cell<u32>,
+        now: Cell<u32>,
+    }
+
+    impl Panel {
+        fn sent(&self, command: Command) -> bool {
+            self.commands.get()[..self.sent.get()].contains(&(command as u8))
+        }
+    }
+
+    struct Spi<'a>(&'a Panel);
+
+    impl Write<u8> for Spi<'_> {
+        type Error = Infallible;
+
+        fn write(&mut self, words: &[u8]) -> Result<(), Infallible> {
+            if self.0.data.get() {
+                return Ok(());
+            }
+            let command = words[0];
+            let mut commands = self.0.commands.get();
+            commands[self.0.sent.get()] = command;
+            self.0.commands.set(commands);
+            self.0.sent.set(self.0.sent.get() + 1);
+            let polls = match command {
+                c if c == Command::PowerOn as u8 => 2,
+                c if c == Command::DisplayRefresh as u8 => 5,
+                c if c == Command::PowerOff as u8 => 1,
+                _ => 0,
+            };
+            self.0.busy_polls.set(polls);
+            Ok(())
+        }
+    }
+
+    /// Every pin, only the data/command pin is followed
+    struct Pin<'a>(&'a Panel, bool);
+
+    impl OutputPin for Pin<'_> {
+        type Error = Infallible;
+
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            if self.1 {
+                self.0.data.set(false);
+            }
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            if self.1 {
+                self.0.data.set(true);
+            }
+            Ok(())
+        }
+    }
+
+    struct Busy<'a>(&'a Panel);
+
+    impl IsBusy for Busy<'_> {
+        fn is_busy(&mut self) -> bool {
+            let polls = self.0.busy_polls.get();
+            if polls > 0 {
+                self.0.busy_polls.set(polls - 1);
+                self.0.now.set(self.0.now.get().wrapping_add(100));
+            }
+            polls > 0
+        }
+    }
+
+    /// Records every call and aborts once it sees `abort_in`
+    struct Hook<'a> {
+        panel: &'a Panel,
+        abort_in: Option<RefreshPhase>,
+        calls: [(RefreshPhase, u32); 32],
+        len: usize,
+    }
+
+    impl<'a> Hook<'a> {
+        fn new(panel: &'a Panel, abort_in: Option<RefreshPhase>) -> Self {
+            Hook {
+                panel,
+                abort_in,
+                calls: [(RefreshPhase::Pending, 0); 32],
+                len: 0,
+            }
+        }
+
+        fn calls(&self) -> &[(RefreshPhase, u32)] {
+            &self.calls[..self.len]
+        }
+    }
+
+    impl RefreshHook for Hook<'_> {
+        fn now(&mut self) -> u32 {
+            self.panel.now.get()
+        }
+
+        fn progress(&mut self, phase: RefreshPhase, elapsed: u32) -> Progress {
+            self.calls[self.len] = (phase, elapsed);
+            self.len += 1;
+            if self.abort_in == Some(phase) {
+                Progress::Abort
+            } else {
+                Progress::Continue
+            }
+        }
+    }
+
+    fn epd(panel: &Panel) -> InkyFrame5_7<Spi<'_>, Pin<'_>, Pin<'_>, Pin<'_>> {
+        let epd = InkyFrame5_7::new(
+            &mut Spi(panel),
+            Pin(panel, false),
+            Pin(panel, true),
+            Pin(panel, false),
+            &mut Busy(panel),
+        )
+        .unwrap();
+        panel.sent.set(0);
+        epd
+    }
+
+    #[test]
+    fn aborting_names_the_phase() {
+        let phases = [
+            RefreshPhase::Pending,
+            RefreshPhase::PowerOn,
+            RefreshPhase::Refresh,
+            RefreshPhase::PowerOff,
+        ];
+        let next = [Command::PowerOn, Command::DisplayRefresh, Command::PowerOff];
+        for (i, phase) in phases.into_iter().enumerate() {
+            let panel = Panel::default();
+            let mut epd = epd(&panel);
+            panel.busy_polls.set(3);
+            let mut hook = Hook::new(&panel, Some(phase));
+            assert_eq!(
+                epd.display_frame_with(&mut Spi(&panel), &mut Busy(&panel), &mut hook),
+                Err(RefreshError::Aborted(phase))
+            );
+            // nothing is sent after the abort
+            for (j, command) in next.into_iter().enumerate() {
+                assert_eq!(panel.sent(command), j < i, "{:?}", phase);
+            }
+            // the hook is asked once in the phase it aborts
+            let calls = hook.calls();
+            assert_eq!(calls.last().map(|call| call.0), Some(phase));
+            assert_eq!(calls.iter().filter(|call| call.0 == phase).count(), 1);
+        }
+    }
+
+    #[test]
+    fn elapsed_counts_from_the_start() {
+        for start in [5000, u32::MAX - 150] {
+            let panel = Panel::default();
+            let mut epd = epd(&panel);
+            panel.now.set(start);
+            panel.busy_polls.set(3);
+            let mut hook = Hook::new(&panel, None);
+            epd.display_frame_with(&mut Spi(&panel), &mut Busy(&panel), &mut hook)
+                .unwrap();
+
+            let calls = hook.calls();
+            assert_eq!(calls.len(), 4 + 3 + 6 + 2);
+            assert_eq!(calls[0], (RefreshPhase::Pending, 0));
+            assert_eq!(calls[3], (RefreshPhase::Pending, 300));
+            assert_eq!(calls[4], (RefreshPhase::PowerOn, 300));
+            assert_eq!(calls[7], (RefreshPhase::Refresh, 500));
+            assert_eq!(calls[13], (RefreshPhase::PowerOff, 1000));
+            assert_eq!(calls[14], (RefreshPhase::PowerOff, 1100));
+            assert!(calls.windows(2).all(|pair| pair[0].1 <= pair[1].1));
+        }
+    }
+
+    #[test]
+    fn without_a_hook_every_phase_runs() {
+        let panel = Panel::default();
+        let mut epd = epd(&panel);
+        panel.busy_polls.set(3);
+        epd.display_frame(&mut Spi(&panel), &mut Busy(&panel))
+            .unwrap();
+        assert!(panel.sent(Command::PowerOff));
+        assert_eq!(panel.busy_polls.get(), 0);
     }
 }
@@ -0,0 +1,189 @@
+//! Fixed point conversion from sRGB to the OKLab color space
+//!
+//! Distances in OKLab follow how different colors look, unlike distances
+//! between the raw RGB values. See https://bottosson.github.io/posts/oklab/
+//!
+//! Everything is integer math, so it works without floating point support and
+//! in const contexts, which lets palettes precompute their colors.
+
+/// sRGB channel value to linear light, 0..=65535
+const SRGB_TO_LINEAR: [u16; 256] = [
+    0, 20, 40, 60, 80, 99, 119, 139, 159, 179, 199, 219, 241, 264, 288, 313, 340, 367, 396, 427,
+    458, 491, 526, 562, 599, 637, 677, 718, 761, 805, 851, 898, 947, 997, 1048, 1101, 1156, 1212,
+    1270, 1330, 1391, 1453, 1517, 1583, 1651, 1720, 1790, 1863, 1937, 2013, 2090, 2170, 2250, 2333,
+    2418, 2504, 2592, 2681, 2773, 2866, 2961, 3058, 3157, 3258, 3360, 3464, 3570, 3678, 3788, 3900,
+    4014, 4129, 4247, 4366, 4488, 4611, 4736, 4864, 4993, 5124, 5257, 5392, 5530, 5669, 5810, 5953,
+    6099, 6246, 6395, 6547, 6700, 6856, 7014, 7174, 7335, 7500, 7666, 7834, 8004, 8177, 8352, 8528,
+    8708, 8889, 9072, 9258, 9445, 9635, 9828, 10022, 10219, 10417, 10619, 10822, 11028, 11235,
+    11446, 11658, 11873, 12090, 12309, 12530, 12754, 12980, 13209, 13440, 13673, 13909, 14146,
+    14387, 14629, 14874, 15122, 15371, 15623, 15878, 16135, 16394, 16656, 16920, 17187, 17456,
+    17727, 18001, 18277, 18556, 18837, 19121, 19407, 19696, 19987, 20281, 20577, 20876, 21177,
+    21481, 21787, 22096, 22407, 22721, 23038, 23357, 23678, 24002, 24329, 24658, 24990, 25325,
+    25662, 26001, 26344, 26688, 27036, 27386, 27739, 28094, 28452, 28813, 29176, 29542, 29911,
+    30282, 30656, 31033, 31412, 31794, 32179, 32567, 32957, 33350, 33745, 34143, 34544, 34948,
+    35355, 35764, 36176, 36591, 37008, 37429, 37852, 38278, 38706, 39138, 39572, 40009, 40449,
+    40891, 41337, 41785, 42236, 42690, 43147, 43606, 44069, 44534, 45002, 45473, 45947, 46423,
+    46903, 47385, 47871, 48359, 48850, 49344, 49841, 50341, 50844, 51349, 51858, 52369, 52884,
+    53401, 53921, 54445, 54971, 55500, 56032, 56567, 57105, 57646, 58190, 58737, 59287, 59840,
+    60396, 60955, 61517, 62082, 62650, 63221, 63795, 64372, 64952, 65535,
+];
+
+/// Linear sRGB to cone responses (LMS), Q12
+const RGB_TO_LMS: [[i64; 3]; 3] = [[1688, 2197, 211], [868, 2788, 440], [362, 1154, 2580]];
+
+/// Cube rooted cone responses to OKLab, Q12
+const LMS_TO_LAB: [[i64; 3]; 3] = [[862, 3251, -17], [8102, -9948, 1846], [106, 3206, -3312]];
+
+/// OKLab coordinates of an 8 bit sRGB color, L in 0..=65536 and a, b scaled the same
+pub(crate) const fn oklab(r: u8, g: u8, b: u8) -> [i32; 3] {
+    let linear = [
+        SRGB_TO_LINEAR[r as usize] as i64,
+        SRGB_TO_LINEAR[g as usize] as i64,
+        SRGB_TO_LINEAR[b as usize] as i64,
+    ];
+    let lms = mul(&RGB_TO_LMS, [linear[0], linear[1], linear[2]]);
+    let lab = mul(&LMS_TO_LAB, [cbrt(lms[0]), cbrt(lms[1]), cbrt(lms[2])]);
+    [lab[0] as i32, lab[1] as i32, lab[2] as i32]
+}
+
+/// Squared distance between two OKLab colors
+pub(crate) const fn distance(a: &[i32; 3], b: &[i32; 3]) -> i64 {
+    let dl = (a[0] - b[0]) as i64;
+    let da = (a[1] - b[1]) as i64;
+    let db = (a[2] - b[2]) as i64;
+    dl * dl + da * da + db * db
+}
+
+const fn mul(matrix: &[[i64; 3]; 3], v: [i64; 3]) -> [i64; 3] {
+    let mut out = [0; 3];
+    let mut i = 0;
+    while i < 3 {
+        out[i] = (matrix[i][0] * v[0] + matrix[i][1] * v[1] + matrix[i][2] * v[2]) >> 12;
+        i += 1;
+    }
+    out
+}
+
+/// Cube roots of `i << 8` for every `i` in 0..=256, see [`cbrt`]
+const CBRT: [u32; 257] = {
+    let mut table = [0; 257];
+    let mut i = 0;
+    while i < table.len() {
+        table[i] = exact_cbrt((i as i64) << 8) as u32;
+        i += 1;
+    }
+    table
+};
+
+/// Cube root of a value in 0..=65536 representing 0.0..=1.0, same scale
+///
+/// Small values are scaled up by powers of 8 so the table is only used above
+/// 8192 where it is nearly straight. Interpolating between its entries is
+/// within 2 of the exact root, without any division.
+const fn cbrt(x: i64) -> i64 {
+    if x <= 0 {
+        return 0;
+    }
+    let x = if x > 65536 { 65536 } else { x };
+    let (mut scaled, mut halvings) = (x, 0);
+    while scaled < 8192 {
+        scaled <<= 3;
+        halvings += 1;
+    }
+    let i = if scaled >> 8 < 256 { scaled >> 8 } else { 255 } as usize;
+    let frac = scaled - ((i as i64) << 8);
+    let (low, high) = (CBRT[i] as i64, CBRT[i + 1] as i64);
+    (low + (((high - low) * frac) >> 8)) >> halvings
+}
+
+/// [`cbrt`] by bisection, only used to build the table
+const fn exact_cbrt(x: i64) -> i64 {
+    if x <= 0 {
+        return 0;
+    }
+    let target = (x as u64) << 32;
+    let (mut low, mut high) = (0u64, 1u64 << 17);
+    while low < high {
+        let mid = (low + high).div_ceil(2);
+        if mid * mid * mid <= target {
+            low = mid;
+        } else {
+            high = mid - 1;
+        }
+    }
+    low as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cbrt_is_close_to_bisection() {
+        for x in 0..=65536 {
+            assert!(cbrt(x).abs_diff(exact_cbrt(x)) <= 2, "cbrt({x})");
+        }
+        assert_eq!(cbrt(65536), 65536);
+        assert_eq!(cbrt(-5), 0);
+    }
+
+    #[test]
+    fn matches_reference_values() {
+        // from https://bottosson.github.io/posts/oklab/, scaled by 65536
+        let references = [
+            ((255, 255, 255), [65536, 0, 0]),
+            ((0, 0, 0), [0, 0, 0]),
+            ((255, 0, 0), [41154, 14737, 8247]),
+            ((0, 255, 0), [56783, -15328, 11764]),
+            ((0, 0, 255), [29623, -2127, -20416]),
+        ];
+        for ((r, g, b), expected) in references {
+            let lab = oklab(r, g, b);
+            for (value, expected) in lab.iter().zip(expected) {
+                assert!(
+                    value.abs_diff(expected) <= 256,
+                    "({r}, {g}, {b}): {lab:?} != {expected:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn grays_have_no_hue() {
+        let mut last = -1;
+        for v in 0..=255 {
+            let [l, a, b] = oklab(v, v, v);
+            assert!(l > last, "{v}");
+            assert!(a.abs() <= 64 && b.abs() <= 64, "{v}: {a} {b}");
+            last = l;
+        }
+    }
+
+    #[test]
+    fn quantizes_to_the_closest_looking_ink() {
+        let inks: [(u8, u8, u8); 7] = [
+            (0, 0, 0),
+            (255, 255, 255),
+            (0, 255, 0),
+            (0, 0, 255),
+            (255, 0, 0),
+            (255, 255, 0),
+            (255, 128, 0),
+        ];
+        let lab = inks.map(|(r, g, b)| oklab(r, g, b));
+        let nearest = |r, g, b| {
+            let color = oklab(r, g, b);
+            (0..inks.len())
+                .min_by_key(|i| distance(&lab[*i], &color))
+                .unwrap()
+        };
+        for (i, (r, g, b)) in inks.into_iter().enumerate() {
+            assert_eq!(nearest(r, g, b), i);
+        }
+        assert_eq!(nearest(200, 30, 20), 4);
+        assert_eq!(nearest(30, 60, 200), 3);
+        assert_eq!(nearest(255, 160, 40), 6);
+        assert_eq!(nearest(40, 40, 50), 0);
+        assert_eq!(nearest(230, 230, 220), 1);
+    }
+}
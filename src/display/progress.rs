@@ -0,0 +1,59 @@
+use super::traits::RefreshHook;
+
+/// What the panel is busy with while a [`RefreshHook`] is called
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RefreshPhase {
+    /// Finishing whatever it was busy with before the call, e.g. an earlier
+    /// refresh, this is the only wait before sending a frame
+    Pending,
+    /// Powering up the booster
+    PowerOn,
+    /// Driving the pixels, this takes most of the time
+    Refresh,
+    /// Powering down the booster
+    PowerOff,
+}
+
+/// What a [`RefreshHook`] wants the driver to do next
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Progress {
+    /// Keep waiting for the panel
+    #[default]
+    Continue,
+    /// Stop waiting and return from the refresh
+    Abort,
+}
+
+/// Hook that never aborts, used by the methods that don't take one
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct NoRefreshHook;
+
+impl RefreshHook for NoRefreshHook {
+    fn now(&mut self) -> u32 {
+        0
+    }
+
+    fn progress(&mut self, _phase: RefreshPhase, _elapsed: u32) -> Progress {
+        Progress::Continue
+    }
+}
+
+/// When a refresh with a [`RefreshHook`] didn't finish
+#[derive(Debug, PartialEq, Eq)]
+pub enum RefreshError<E> {
+    /// Writing to the panel failed
+    Spi(E),
+    /// The hook aborted while the panel was busy with this phase
+    Aborted(RefreshPhase),
+}
+
+impl<E: core::fmt::Debug> core::fmt::Display for RefreshError<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            RefreshError::Spi(error) => write!(f, "SPI error during refresh: {:?}", error),
+            RefreshError::Aborted(phase) => write!(f, "Refresh aborted during {:?}", phase),
+        }
+    }
+}
@@ -0,0 +1,269 @@
+//! Swapping colors of content that is already drawn, or while it's drawn
+//!
+//! A [`ColorMap`] says which color every [`OctColor`] turns into, e.g. to
+//! switch between a day and a night theme. Buffers apply it with a lookup
+//! table over whole bytes (see
+//! [`OctDisplay::recolor`](super::OctDisplay::recolor)) and a
+//! [`RecolorDrawTarget`] applies it to everything drawn through it.
+use embedded_graphics::{prelude::*, primitives::Rectangle};
+
+use super::color::OctColor;
+
+/// Replacement for every color, indexed by the color's nibble
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ColorMap([OctColor; 8]);
+
+impl Default for ColorMap {
+    fn default() -> Self {
+        ColorMap::IDENTITY
+    }
+}
+
+impl ColorMap {
+    /// Keeps every color as it is
+    pub const IDENTITY: ColorMap = ColorMap([
+        OctColor::Black,
+        OctColor::White,
+        OctColor::Green,
+        OctColor::Blue,
+        OctColor::Red,
+        OctColor::Yellow,
+        OctColor::Orange,
+        OctColor::HiZ,
+    ]);
+
+    /// Swaps black and white, leaving the other colors
+    pub const INVERT: ColorMap = ColorMap::IDENTITY
+        .with(OctColor::Black, OctColor::White)
+        .with(OctColor::White, OctColor::Black);
+
+    /// Creates a map from the replacements in the order of the colors' nibbles
+    pub const fn new(map: [OctColor; 8]) -> ColorMap {
+        ColorMap(map)
+    }
+
+    /// Replaces `from` with `to`
+    pub const fn with(mut self, from: OctColor, to: OctColor) -> ColorMap {
+        self.0[from as usize] = to;
+        self
+    }
+
+    /// The replacement of a color
+    pub const fn map(&self, color: OctColor) -> OctColor {
+        self.0[color as usize]
+    }
+
+    /// Lookup table that maps both nibbles of a packed buffer byte at once
+    ///
+    /// Nibbles that aren't a color are read the way the panel does, see
+    /// [`OctColor::from_nibble_lossy`]
+    pub fn byte_lut(&self) -> [u8; 256] {
+        let mut lut = [0u8; 256];
+        for (byte, entry) in lut.iter_mut().enumerate() {
+            let upper = self.map(OctColor::from_nibble_lossy(byte as u8 >> 4));
+            let lower = self.map(OctColor::from_nibble_lossy(byte as u8));
+            *entry = OctColor::colors_byte(upper, lower);
+        }
+        lut
+    }
+}
+
+/// Draw target adapter that swaps colors on the way to the display
+pub struct RecolorDrawTarget<'a, D> {
+    target: &'a mut D,
+    map: ColorMap,
+}
+
+impl<'a, D> RecolorDrawTarget<'a, D>
+where
+    D: DrawTarget<Color = OctColor>,
+{
+    pub fn new(target: &'a mut D, map: ColorMap) -> Self {
+        RecolorDrawTarget { target, map }
+    }
+}
+
+impl<D> Dimensions for RecolorDrawTarget<'_, D>
+where
+    D: DrawTarget<Color = OctColor>,
+{
+    fn bounding_box(&self) -> Rectangle {
+        self.target.bounding_box()
+    }
+}
+
+impl<D> DrawTarget for RecolorDrawTarget<'_, D>
+where
+    D: DrawTarget<Color = OctColor>,
+{
+    type Color = OctColor;
+    type Error = D::Error;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        let map = self.map;
+        self.target.draw_iter(
+            pixels
+                .into_iter()
+                .map(|Pixel(point, color)| Pixel(point, map.map(color))),
+        )
+    }
+
+    fn fill_contiguous<I>(&mut self, area: &Rectangle, colors: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Self::Color>,
+    {
+        let map = self.map;
+        self.target
+            .fill_contiguous(area, colors.into_iter().map(|color| map.map(color)))
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        self.target.fill_solid(area, self.map.map(color))
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        self.target.clear(self.map.map(color))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::{DisplayRotation, InkyFrameDisplay, OctDisplay};
+    use embedded_graphics::image::GetPixel;
+
+    const ROTATIONS: [DisplayRotation; 4] = [
+        DisplayRotation::Rotate0,
+        DisplayRotation::Rotate90,
+        DisplayRotation::Rotate180,
+        DisplayRotation::Rotate270,
+    ];
+
+    const SHIFT: ColorMap = ColorMap::new([
+        OctColor::White,
+        OctColor::Green,
+        OctColor::Blue,
+        OctColor::Red,
+        OctColor::Yellow,
+        OctColor::Orange,
+        OctColor::Black,
+        OctColor::HiZ,
+    ]);
+
+    fn color(p: Point) -> OctColor {
+        OctColor::from_nibble_lossy((p.x + 3 * p.y).rem_euclid(7) as u8)
+    }
+
+    // fills the corner the areas below are in with a pattern
+    fn patterned(rotation: DisplayRotation) -> InkyFrameDisplay {
+        let mut display = InkyFrameDisplay::default();
+        display.set_rotation(rotation);
+        let corner = Rectangle::new(Point::zero(), Size::new(16, 16));
+        display
+            .draw_iter(corner.points().map(|p| Pixel(p, color(p))))
+            .unwrap();
+        display
+    }
+
+    #[test]
+    fn byte_lut_maps_both_nibbles() {
+        let identity = ColorMap::IDENTITY.byte_lut();
+        let shift = SHIFT.byte_lut();
+        for upper in 0..8u8 {
+            for lower in 0..8u8 {
+                let byte = upper << 4 | lower;
+                assert_eq!(identity[byte as usize], byte);
+                let (a, b) = OctColor::split_byte(byte).unwrap();
+                assert_eq!(
+                    shift[byte as usize],
+                    OctColor::colors_byte(SHIFT.map(a), SHIFT.map(b))
+                );
+            }
+        }
+        // nibbles that aren't colors are read like the panel does
+        assert_eq!(identity[0x9f], 0x17);
+        assert_eq!(ColorMap::INVERT.byte_lut()[0x98], 0x01);
+    }
+
+    #[test]
+    fn recolor_region_maps_only_the_area() {
+        let areas = [
+            Rectangle::new(Point::new(1, 1), Size::new(5, 3)),
+            Rectangle::new(Point::new(2, 0), Size::new(3, 2)),
+            Rectangle::new(Point::new(3, 5), Size::new(1, 1)),
+            Rectangle::new(Point::new(4, 2), Size::new(2, 7)),
+            Rectangle::new(Point::new(-2, -1), Size::new(5, 4)),
+        ];
+        for (r, rotation) in ROTATIONS.into_iter().enumerate() {
+            for area in areas {
+                let mut display = patterned(rotation);
+                display.recolor_region(&area, &SHIFT);
+                for p in Rectangle::new(Point::zero(), Size::new(16, 16)).points() {
+                    let expected = if area.contains(p) {
+                        SHIFT.map(color(p))
+                    } else {
+                        color(p)
+                    };
+                    assert_eq!(
+                        display.pixel(p),
+                        Some(expected),
+                        "rotation {} {:?} {:?}",
+                        r,
+                        area,
+                        p
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn recolor_maps_everything() {
+        let mut display = patterned(DisplayRotation::Rotate0);
+        display.recolor(&SHIFT);
+        for p in Rectangle::new(Point::zero(), Size::new(16, 16)).points() {
+            assert_eq!(display.pixel(p), Some(SHIFT.map(color(p))));
+        }
+        assert_eq!(
+            display.pixel(Point::new(100, 100)),
+            Some(SHIFT.map(OctColor::White))
+        );
+    }
+
+    #[test]
+    fn draw_target_maps_drawn_colors() {
+        let mut display = InkyFrameDisplay::default();
+        display.set_rotation(DisplayRotation::Rotate0);
+        let mut target = RecolorDrawTarget::new(&mut display, ColorMap::INVERT);
+        target.clear(OctColor::Black).unwrap();
+        Pixel(Point::new(0, 0), OctColor::White)
+            .draw(&mut target)
+            .unwrap();
+        Pixel(Point::new(1, 0), OctColor::Red)
+            .draw(&mut target)
+            .unwrap();
+        target
+            .fill_solid(
+                &Rectangle::new(Point::new(0, 1), Size::new(2, 1)),
+                OctColor::Black,
+            )
+            .unwrap();
+        target
+            .fill_contiguous(
+                &Rectangle::new(Point::new(0, 2), Size::new(2, 1)),
+                [OctColor::Blue, OctColor::White],
+            )
+            .unwrap();
+
+        assert_eq!(display.pixel(Point::new(0, 0)), Some(OctColor::Black));
+        assert_eq!(display.pixel(Point::new(1, 0)), Some(OctColor::Red));
+        assert_eq!(display.pixel(Point::new(0, 1)), Some(OctColor::White));
+        assert_eq!(display.pixel(Point::new(1, 1)), Some(OctColor::White));
+        assert_eq!(display.pixel(Point::new(0, 2)), Some(OctColor::Blue));
+        assert_eq!(display.pixel(Point::new(1, 2)), Some(OctColor::Black));
+        assert_eq!(display.pixel(Point::new(5, 5)), Some(OctColor::White));
+    }
+}
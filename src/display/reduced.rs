@@ -0,0 +1,453 @@
+//! Frame buffers that need less memory than [`InkyFrameDisplay`](super::InkyFrameDisplay)
+//!
+//! They trade resolution or the amount of colors for RAM and get expanded to
+//! the panel's format while they are streamed with
+//! [`update_frame_from`](super::InkyFrame5_7::update_frame_from).
+use embedded_graphics::{image::GetPixel, prelude::*, primitives::Rectangle};
+
+use super::{
+    color::{ColorSet, OctColor, Palette},
+    display::{
+        draw_helper, fill_contiguous_helper, fill_solid_helper, find_rotation, logical_size,
+        outside_display, pixel_helper, DisplayRotation, OctDisplay,
+    },
+    traits::FrameSource,
+    DEFAULT_BACKGROUND_COLOR, HEIGHT, WIDTH,
+};
+
+const HALF_WIDTH: u32 = WIDTH / 2;
+const HALF_HEIGHT: u32 = HEIGHT / 2;
+const HALF_BUFFER_SIZE: usize = HALF_WIDTH as usize / 2 * HALF_HEIGHT as usize;
+
+/// Buffer with half the resolution in both directions (300x224)
+///
+/// Every pixel is shown as a 2x2 block on the panel. Needs a quarter of the
+/// memory of [`InkyFrameDisplay`](super::InkyFrameDisplay).
+pub struct HalfResolutionDisplay {
+    buffer: [u8; HALF_BUFFER_SIZE],
+    rotation: DisplayRotation,
+}
+
+impl Default for HalfResolutionDisplay {
+    fn default() -> Self {
+        HalfResolutionDisplay {
+            buffer: [OctColor::colors_byte(DEFAULT_BACKGROUND_COLOR, DEFAULT_BACKGROUND_COLOR);
+                HALF_BUFFER_SIZE],
+            rotation: DisplayRotation::default(),
+        }
+    }
+}
+
+/// The buffer is packed like the panel's buffer but at half resolution
+impl OctDisplay for HalfResolutionDisplay {
+    const WIDTH: u32 = HALF_WIDTH;
+    const HEIGHT: u32 = HALF_HEIGHT;
+
+    fn buffer(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    fn get_mut_buffer(&mut self) -> &mut [u8] {
+        &mut self.buffer
+    }
+
+    fn set_rotation(&mut self, rotation: DisplayRotation) {
+        self.rotation = rotation;
+    }
+
+    fn rotation(&self) -> DisplayRotation {
+        self.rotation
+    }
+}
+
+impl DrawTarget for HalfResolutionDisplay {
+    type Color = OctColor;
+    type Error = core::convert::Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for pixel in pixels {
+            draw_helper(
+                &mut self.buffer,
+                HALF_WIDTH,
+                HALF_HEIGHT,
+                self.rotation,
+                pixel,
+            )?;
+        }
+        Ok(())
+    }
+
+    fn fill_contiguous<I>(&mut self, area: &Rectangle, colors: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Self::Color>,
+    {
+        fill_contiguous_helper(
+            &mut self.buffer,
+            HALF_WIDTH,
+            HALF_HEIGHT,
+            self.rotation,
+            area,
+            colors,
+        );
+        Ok(())
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        fill_solid_helper(
+            &mut self.buffer,
+            HALF_WIDTH,
+            HALF_HEIGHT,
+            self.rotation,
+            area,
+            color,
+        );
+        Ok(())
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        self.clear_buffer(color);
+        Ok(())
+    }
+}
+
+impl OriginDimensions for HalfResolutionDisplay {
+    fn size(&self) -> Size {
+        logical_size(HALF_WIDTH, HALF_HEIGHT, self.rotation)
+    }
+}
+
+impl GetPixel for HalfResolutionDisplay {
+    type Color = OctColor;
+
+    fn pixel(&self, p: Point) -> Option<Self::Color> {
+        pixel_helper(&self.buffer, HALF_WIDTH, HALF_HEIGHT, self.rotation, p)
+    }
+}
+
+impl FrameSource for HalfResolutionDisplay {
+    fn panel_row(&self, y: u32, row: &mut [u8]) {
+        let start = (y / 2) as usize * HALF_WIDTH as usize / 2;
+        let Some(src) = self.buffer.get(start..start + HALF_WIDTH as usize / 2) else {
+            return;
+        };
+        // every source nibble becomes a whole panel byte
+        for (pair, byte) in row.chunks_exact_mut(2).zip(src) {
+            pair[0] = (byte & 0xf0) | (byte >> 4);
+            pair[1] = (byte << 4) | (byte & 0x0f);
+        }
+    }
+}
+
+/// Full resolution buffer with 1 bit per pixel and 2 colors
+pub type MonoPaletteDisplay = PaletteDisplay<1, { WIDTH as usize * HEIGHT as usize / 8 }>;
+/// Full resolution buffer with 2 bits per pixel and 4 colors
+pub type QuadPaletteDisplay = PaletteDisplay<2, { WIDTH as usize * HEIGHT as usize / 4 }>;
+
+/// Full resolution buffer that only stores an index into a small set of colors
+///
+/// Uses `BITS` (1 or 2) bits per pixel, so it can hold 2 or 4 of the
+/// [`OctColor`]s. Drawing a color that isn't in the set picks the closest one.
+/// Use [`MonoPaletteDisplay`] or [`QuadPaletteDisplay`] rather than spelling
+/// out the buffer size `N`.
+pub struct PaletteDisplay<const BITS: usize, const N: usize> {
+    buffer: [u8; N],
+    colors: [OctColor; 4],
+    /// Restricted to `colors` to find the closest one
+    palette: Palette,
+    /// Panel byte for every combination of two neighbouring indices
+    pairs: [u8; 16],
+    rotation: DisplayRotation,
+}
+
+impl<const BITS: usize, const N: usize> PaletteDisplay<BITS, N> {
+    const COLORS: usize = 1 << BITS;
+    const MASK: u8 = (1 << BITS) - 1;
+
+    /// Creates the buffer with the given colors, exactly `2^BITS` of them
+    ///
+    /// The buffer starts out filled with the first color
+    pub fn new(colors: &[OctColor]) -> Result<Self, InvalidPaletteLengthError> {
+        const {
+            assert!(
+                BITS == 1 || BITS == 2,
+                "only 1 or 2 bits per pixel are supported"
+            );
+            assert!(N == WIDTH as usize * HEIGHT as usize * BITS / 8);
+        }
+        if colors.len() != Self::COLORS {
+            return Err(InvalidPaletteLengthError(colors.len()));
+        }
+
+        let mut palette = [colors[0]; 4];
+        palette[..Self::COLORS].copy_from_slice(colors);
+        let mut pairs = [0u8; 16];
+        for a in 0..Self::COLORS {
+            for b in 0..Self::COLORS {
+                pairs[a << BITS | b] = OctColor::colors_byte(palette[a], palette[b]);
+            }
+        }
+
+        Ok(PaletteDisplay {
+            buffer: [0; N],
+            colors: palette,
+            palette: Palette::SATURATED.with_candidates(ColorSet::from_colors(colors)),
+            pairs,
+            rotation: DisplayRotation::default(),
+        })
+    }
+
+    /// The colors the buffer can hold
+    pub fn colors(&self) -> &[OctColor] {
+        &self.colors[..Self::COLORS]
+    }
+
+    /// Clears the buffer with the closest available color
+    pub fn clear_buffer(&mut self, background_color: OctColor) {
+        let index = self.index_of(background_color);
+        let mut byte = 0u8;
+        for _ in 0..8 / BITS {
+            byte = byte << BITS | index;
+        }
+        self.buffer.fill(byte);
+    }
+
+    /// Returns the buffer of packed color indices
+    pub fn buffer(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    /// Sets the rotation of the display
+    pub fn set_rotation(&mut self, rotation: DisplayRotation) {
+        self.rotation = rotation;
+    }
+
+    // index of the color, or of the closest color if it isn't available
+    fn index_of(&self, color: OctColor) -> u8 {
+        let colors = self.colors();
+        if let Some(index) = colors.iter().position(|c| *c == color) {
+            return index as u8;
+        }
+
+        let (r, g, b) = color.rgb();
+        let nearest = self.palette.nearest(r, g, b);
+        colors.iter().position(|c| *c == nearest).unwrap_or(0) as u8
+    }
+
+    // byte index and shift of the panel pixel at (x, y)
+    fn position(x: u32, y: u32) -> (usize, u32) {
+        let bit = (y as usize * WIDTH as usize + x as usize) * BITS;
+        (bit / 8, (8 - BITS - bit % 8) as u32)
+    }
+}
+
+impl<const BITS: usize, const N: usize> DrawTarget for PaletteDisplay<BITS, N> {
+    type Color = OctColor;
+    type Error = core::convert::Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            if outside_display(point, WIDTH, HEIGHT, self.rotation) {
+                continue;
+            }
+            let (x, y) =
+                find_rotation(point.x as u32, point.y as u32, WIDTH, HEIGHT, self.rotation);
+            let (index, shift) = Self::position(x, y);
+            let value = self.index_of(color);
+            if let Some(byte) = self.buffer.get_mut(index) {
+                *byte = (*byte & !(Self::MASK << shift)) | (value << shift);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<const BITS: usize, const N: usize> OriginDimensions for PaletteDisplay<BITS, N> {
+    fn size(&self) -> Size {
+        logical_size(WIDTH, HEIGHT, self.rotation)
+    }
+}
+
+impl<const BITS: usize, const N: usize> GetPixel for PaletteDisplay<BITS, N> {
+    type Color = OctColor;
+
+    fn pixel(&self, p: Point) -> Option<Self::Color> {
+        if outside_display(p, WIDTH, HEIGHT, self.rotation) {
+            return None;
+        }
+        let (x, y) = find_rotation(p.x as u32, p.y as u32, WIDTH, HEIGHT, self.rotation);
+        let (index, shift) = Self::position(x, y);
+        let value = (self.buffer.get(index)? >> shift) & Self::MASK;
+        Some(self.colors[value as usize])
+    }
+}
+
+impl<const BITS: usize, const N: usize> FrameSource for PaletteDisplay<BITS, N> {
+    fn panel_row(&self, y: u32, row: &mut [u8]) {
+        let row_bytes = WIDTH as usize * BITS / 8;
+        let start = y as usize * row_bytes;
+        let Some(src) = self.buffer.get(start..start + row_bytes) else {
+            return;
+        };
+        // each source byte holds 4 / BITS pairs of pixels, i.e. panel bytes
+        let pairs_per_byte = 4 / BITS;
+        let pair_mask = (1u8 << (2 * BITS)) - 1;
+        for (out, byte) in row.chunks_exact_mut(pairs_per_byte).zip(src) {
+            for (k, panel_byte) in out.iter_mut().enumerate() {
+                let shift = 8 - 2 * BITS * (k + 1);
+                *panel_byte = self.pairs[((byte >> shift) & pair_mask) as usize];
+            }
+        }
+    }
+}
+
+/// When the amount of colors doesn't match the bits per pixel of a [`PaletteDisplay`]
+#[derive(Debug, PartialEq, Eq)]
+pub struct InvalidPaletteLengthError(pub usize);
+impl core::fmt::Display for InvalidPaletteLengthError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Palette has the wrong amount of colors: {}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::{InkyFrameDisplay, BUFFER_SIZE};
+
+    const ROW_BYTES: usize = WIDTH as usize / 2;
+
+    fn panel_row(source: &impl FrameSource, y: u32) -> [u8; ROW_BYTES] {
+        let mut row = [0xaa; ROW_BYTES];
+        source.panel_row(y, &mut row);
+        row
+    }
+
+    #[test]
+    fn half_resolution_rows_are_doubled() {
+        let mut display = HalfResolutionDisplay::default();
+        display.set_rotation(DisplayRotation::Rotate0);
+        display.clear_buffer(OctColor::White);
+        Pixel(Point::new(0, 0), OctColor::Red)
+            .draw(&mut display)
+            .unwrap();
+        Pixel(Point::new(1, 0), OctColor::Blue)
+            .draw(&mut display)
+            .unwrap();
+        Pixel(Point::new(HALF_WIDTH as i32 - 1, 1), OctColor::Green)
+            .draw(&mut display)
+            .unwrap();
+        assert_eq!(display.pixel(Point::new(1, 0)), Some(OctColor::Blue));
+
+        let white = OctColor::colors_byte(OctColor::White, OctColor::White);
+        for y in [0, 1] {
+            let row = panel_row(&display, y);
+            assert_eq!(row[0], OctColor::colors_byte(OctColor::Red, OctColor::Red));
+            assert_eq!(
+                row[1],
+                OctColor::colors_byte(OctColor::Blue, OctColor::Blue)
+            );
+            assert!(row[2..].iter().all(|&b| b == white));
+        }
+        for y in [2, 3] {
+            let row = panel_row(&display, y);
+            assert_eq!(
+                row[ROW_BYTES - 1],
+                OctColor::colors_byte(OctColor::Green, OctColor::Green)
+            );
+            assert!(row[..ROW_BYTES - 1].iter().all(|&b| b == white));
+        }
+    }
+
+    #[test]
+    fn rows_past_the_end_are_left_alone() {
+        let display = HalfResolutionDisplay::default();
+        assert_eq!(panel_row(&display, HEIGHT), [0xaa; ROW_BYTES]);
+        let display = MonoPaletteDisplay::new(&[OctColor::Black, OctColor::White]).unwrap();
+        assert_eq!(panel_row(&display, HEIGHT), [0xaa; ROW_BYTES]);
+        assert_eq!(panel_row(&display, u32::MAX), [0xaa; ROW_BYTES]);
+    }
+
+    fn round_trip<const BITS: usize, const N: usize>(colors: &[OctColor]) {
+        let mut display = PaletteDisplay::<BITS, N>::new(colors).unwrap();
+        display.set_rotation(DisplayRotation::Rotate0);
+        let points = [
+            (0, 0),
+            (1, 0),
+            (2, 0),
+            (7, 0),
+            (8, 0),
+            (5, 3),
+            (WIDTH - 1, HEIGHT - 1),
+        ];
+        for (i, &(x, y)) in points.iter().enumerate() {
+            let color = colors[(i + 1) % colors.len()];
+            Pixel(Point::new(x as i32, y as i32), color)
+                .draw(&mut display)
+                .unwrap();
+        }
+
+        let mut panel = InkyFrameDisplay::default();
+        panel.set_rotation(DisplayRotation::Rotate0);
+        panel.clear_buffer(colors[0]);
+        for (i, &(x, y)) in points.iter().enumerate() {
+            let color = colors[(i + 1) % colors.len()];
+            let point = Point::new(x as i32, y as i32);
+            assert_eq!(display.pixel(point), Some(color), "{:?}", point);
+            Pixel(point, color).draw(&mut panel).unwrap();
+        }
+        for y in 0..HEIGHT {
+            let start = y as usize * ROW_BYTES;
+            assert_eq!(
+                panel_row(&display, y)[..],
+                panel.buffer()[start..start + ROW_BYTES],
+                "row {}",
+                y
+            );
+        }
+    }
+
+    #[test]
+    fn mono_round_trip() {
+        round_trip::<1, { BUFFER_SIZE / 4 }>(&[OctColor::White, OctColor::Black]);
+    }
+
+    #[test]
+    fn quad_round_trip() {
+        round_trip::<2, { BUFFER_SIZE / 2 }>(&[
+            OctColor::White,
+            OctColor::Black,
+            OctColor::Red,
+            OctColor::Yellow,
+        ]);
+    }
+
+    #[test]
+    fn missing_colors_use_the_nearest() {
+        let mut display = QuadPaletteDisplay::new(&[
+            OctColor::White,
+            OctColor::Black,
+            OctColor::Red,
+            OctColor::Blue,
+        ])
+        .unwrap();
+        let point = Point::new(3, 3);
+        Pixel(point, OctColor::Orange).draw(&mut display).unwrap();
+        assert_eq!(display.pixel(point), Some(OctColor::Red));
+        display.clear_buffer(OctColor::Green);
+        assert_eq!(display.pixel(point), Some(OctColor::Black));
+    }
+
+    #[test]
+    fn palette_length_is_checked() {
+        assert_eq!(
+            MonoPaletteDisplay::new(&[OctColor::Black]).err(),
+            Some(InvalidPaletteLengthError(1))
+        );
+    }
+}
@@ -0,0 +1,422 @@
+//! Run-length encoded frame buffer
+//!
+//! Every panel row gets a fixed slot of `ROW_BUDGET` bytes holding runs of a
+//! single color. A run is one byte: the color in the upper 3 bits and the
+//! run length minus one in the lower 5 bits, so a run covers 1 to 32 pixels.
+//!
+//! A single colored row takes `ceil(600 / 32) = 19` bytes, which is also the
+//! smallest allowed budget. The worst case is a row where every pixel differs
+//! from its neighbour: 600 bytes, twice the size of the uncompressed row. Rows
+//! that don't fit into their budget are handled by the [`RleOverflowPolicy`].
+//!
+//! The whole buffer takes `ROW_BUDGET * 448` bytes, e.g. 28 KB with a budget of
+//! 64 bytes compared to the 134 KB of [`InkyFrameDisplay`](super::InkyFrameDisplay).
+use embedded_graphics::{image::GetPixel, prelude::*, primitives::Rectangle};
+
+use super::{
+    color::OctColor,
+    display::{
+        fill_nibbles, find_rotation, logical_size, outside_display, panel_area, set_nibble,
+        DisplayRotation,
+    },
+    traits::FrameSource,
+    DEFAULT_BACKGROUND_COLOR, HEIGHT, WIDTH,
+};
+
+const ROW_BYTES: usize = WIDTH as usize / 2;
+const MAX_RUN: usize = 32;
+/// Smallest row budget, enough for a row of a single color
+pub const MIN_ROW_BUDGET: usize = (WIDTH as usize).div_ceil(MAX_RUN);
+
+/// What to do with a row whose runs don't fit into its budget
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum RleOverflowPolicy {
+    /// Absorb short runs into the run before them until the row fits,
+    /// losing the details of the row
+    #[default]
+    Degrade,
+    /// Keep the previous contents of the row and return a [`RowBudgetExceededError`]
+    Reject,
+}
+
+/// When a row doesn't fit into its budget with [`RleOverflowPolicy::Reject`]
+#[derive(Debug, PartialEq, Eq)]
+pub struct RowBudgetExceededError(pub u32);
+impl core::fmt::Display for RowBudgetExceededError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Row {} doesn't fit into its budget", self.0)
+    }
+}
+
+/// Frame buffer that stores every row run-length encoded
+///
+/// Drawing decodes the touched rows, changes them and encodes them again, so
+/// it is slower than [`InkyFrameDisplay`](super::InkyFrameDisplay) but needs
+/// a fraction of the memory for screens made up of flat regions.
+/// It's sent to the panel with [`update_frame_from`](super::InkyFrame5_7::update_frame_from).
+pub struct RleDisplay<const ROW_BUDGET: usize> {
+    rows: [[u8; ROW_BUDGET]; HEIGHT as usize],
+    rotation: DisplayRotation,
+    policy: RleOverflowPolicy,
+    degraded_rows: u32,
+}
+
+impl<const ROW_BUDGET: usize> Default for RleDisplay<ROW_BUDGET> {
+    fn default() -> Self {
+        const {
+            assert!(
+                ROW_BUDGET >= MIN_ROW_BUDGET,
+                "row budget can't hold a single colored row"
+            );
+        }
+        let mut display = RleDisplay {
+            rows: [[0; ROW_BUDGET]; HEIGHT as usize],
+            rotation: DisplayRotation::default(),
+            policy: RleOverflowPolicy::default(),
+            degraded_rows: 0,
+        };
+        display.clear_buffer(DEFAULT_BACKGROUND_COLOR);
+        display
+    }
+}
+
+impl<const ROW_BUDGET: usize> RleDisplay<ROW_BUDGET> {
+    /// Clears the buffer of the display with the chosen background color
+    pub fn clear_buffer(&mut self, background_color: OctColor) {
+        let mut row = [0u8; ROW_BUDGET];
+        encode_flat(&mut row, background_color);
+        self.rows.fill(row);
+    }
+
+    /// Sets the rotation of the display
+    pub fn set_rotation(&mut self, rotation: DisplayRotation) {
+        self.rotation = rotation;
+    }
+
+    /// Sets how rows that don't fit into their budget are handled
+    pub fn set_overflow_policy(&mut self, policy: RleOverflowPolicy) {
+        self.policy = policy;
+    }
+
+    /// How many times a row had to be degraded to fit since the last reset
+    pub fn degraded_rows(&self) -> u32 {
+        self.degraded_rows
+    }
+
+    /// Resets the [degraded_rows](Self::degraded_rows) counter
+    pub fn reset_degraded_rows(&mut self) {
+        self.degraded_rows = 0;
+    }
+
+    /// Bytes used by the runs of every row, to help with picking a budget
+    pub fn used_bytes(&self) -> usize {
+        self.rows.iter().map(|row| encoded_len(row)).sum()
+    }
+
+    fn load_row(&self, y: u32, row: &mut [u8; ROW_BYTES]) {
+        decode_row(&self.rows[y as usize], row);
+    }
+
+    fn store_row(&mut self, y: u32, row: &[u8; ROW_BYTES]) -> Result<(), RowBudgetExceededError> {
+        let slot = &mut self.rows[y as usize];
+        if encode_row(row, slot, 1) {
+            return Ok(());
+        }
+        match self.policy {
+            RleOverflowPolicy::Reject => Err(RowBudgetExceededError(y)),
+            RleOverflowPolicy::Degrade => {
+                let mut min_run = 2;
+                while min_run < WIDTH as usize && !encode_row(row, slot, min_run) {
+                    min_run *= 2;
+                }
+                // a whole row of one color takes MIN_ROW_BUDGET runs, which always fits
+                if min_run >= WIDTH as usize {
+                    encode_row(row, slot, WIDTH as usize);
+                }
+                self.degraded_rows += 1;
+                Ok(())
+            }
+        }
+    }
+}
+
+impl<const ROW_BUDGET: usize> DrawTarget for RleDisplay<ROW_BUDGET> {
+    type Color = OctColor;
+    type Error = RowBudgetExceededError;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        // keep the last touched row decoded, consecutive pixels are usually on the same row
+        let mut row = [0u8; ROW_BYTES];
+        let mut current = None;
+        for Pixel(point, color) in pixels {
+            if outside_display(point, WIDTH, HEIGHT, self.rotation) {
+                continue;
+            }
+            let (x, y) =
+                find_rotation(point.x as u32, point.y as u32, WIDTH, HEIGHT, self.rotation);
+            if current != Some(y) {
+                if let Some(previous) = current {
+                    self.store_row(previous, &row)?;
+                }
+                self.load_row(y, &mut row);
+                current = Some(y);
+            }
+            set_nibble(&mut row, x as usize, color);
+        }
+        if let Some(previous) = current {
+            self.store_row(previous, &row)?;
+        }
+        Ok(())
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        let Some((x0, x1, y0, y1)) = panel_area(WIDTH, HEIGHT, self.rotation, area) else {
+            return Ok(());
+        };
+        let mut row = [0u8; ROW_BYTES];
+        for y in y0..y1 {
+            self.load_row(y, &mut row);
+            fill_nibbles(&mut row, x0 as usize, x1 as usize, color);
+            self.store_row(y, &row)?;
+        }
+        Ok(())
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        self.clear_buffer(color);
+        Ok(())
+    }
+}
+
+impl<const ROW_BUDGET: usize> OriginDimensions for RleDisplay<ROW_BUDGET> {
+    fn size(&self) -> Size {
+        logical_size(WIDTH, HEIGHT, self.rotation)
+    }
+}
+
+impl<const ROW_BUDGET: usize> GetPixel for RleDisplay<ROW_BUDGET> {
+    type Color = OctColor;
+
+    fn pixel(&self, p: Point) -> Option<Self::Color> {
+        if outside_display(p, WIDTH, HEIGHT, self.rotation) {
+            return None;
+        }
+        let (x, y) = find_rotation(p.x as u32, p.y as u32, WIDTH, HEIGHT, self.rotation);
+        let mut start = 0;
+        for run in self.rows[y as usize].iter() {
+            let (color, len) = split_run(*run);
+            start += len;
+            if x < start as u32 {
+                return Some(OctColor::from_nibble_lossy(color));
+            }
+        }
+        None
+    }
+}
+
+impl<const ROW_BUDGET: usize> FrameSource for RleDisplay<ROW_BUDGET> {
+    fn panel_row(&self, y: u32, row: &mut [u8]) {
+        decode_row(&self.rows[y as usize], row);
+    }
+}
+
+fn split_run(run: u8) -> (u8, usize) {
+    (run >> 5, (run & 0x1f) as usize + 1)
+}
+
+fn make_run(color: u8, len: usize) -> u8 {
+    color << 5 | (len - 1) as u8
+}
+
+// Bytes used by the runs of an encoded row
+fn encoded_len(slot: &[u8]) -> usize {
+    let mut pixels = 0;
+    slot.iter()
+        .take_while(|run| {
+            let more = pixels < WIDTH as usize;
+            pixels += split_run(**run).1;
+            more
+        })
+        .count()
+}
+
+fn encode_flat(slot: &mut [u8], color: OctColor) {
+    let mut remaining = WIDTH as usize;
+    for run in slot.iter_mut() {
+        if remaining == 0 {
+            break;
+        }
+        let len = remaining.min(MAX_RUN);
+        *run = make_run(color.get_nibble(), len);
+        remaining -= len;
+    }
+}
+
+// Expands an encoded row into packed panel bytes
+fn decode_row(slot: &[u8], row: &mut [u8]) {
+    let mut start = 0;
+    for run in slot {
+        if start >= WIDTH as usize {
+            break;
+        }
+        let (color, len) = split_run(*run);
+        let color = OctColor::from_nibble_lossy(color);
+        fill_nibbles(row, start, start + len, color);
+        start += len;
+    }
+}
+
+// Encodes packed panel bytes into the slot, absorbing every run shorter than
+// `min_run` into the run before it. Returns false if the row doesn't fit, in
+// which case the slot is left untouched.
+fn encode_row<const ROW_BUDGET: usize>(
+    row: &[u8; ROW_BYTES],
+    slot: &mut [u8; ROW_BUDGET],
+    min_run: usize,
+) -> bool {
+    let nibble = |i: usize| {
+        let byte = row[i / 2];
+        if i & 0x1 == 0 {
+            byte >> 4
+        } else {
+            byte & 0x0f
+        }
+    };
+
+    let mut encoded = [0u8; ROW_BUDGET];
+    let mut used = 0;
+    let mut x = 0;
+    while x < WIDTH as usize {
+        let color = nibble(x) & 0x7;
+        let mut len = 1;
+        while x + len < WIDTH as usize && (len < min_run || nibble(x + len) & 0x7 == color) {
+            len += 1;
+        }
+        x += len;
+        while len > 0 {
+            let Some(run) = encoded.get_mut(used) else {
+                return false;
+            };
+            let part = len.min(MAX_RUN);
+            *run = make_run(color, part);
+            used += 1;
+            len -= part;
+        }
+    }
+    *slot = encoded;
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Packed row with the color of every pixel given by `color(x)`
+    fn packed(color: impl Fn(usize) -> u8) -> [u8; ROW_BYTES] {
+        let mut row = [0u8; ROW_BYTES];
+        for (i, byte) in row.iter_mut().enumerate() {
+            *byte = color(2 * i) << 4 | color(2 * i + 1);
+        }
+        row
+    }
+
+    #[test]
+    fn rows_round_trip() {
+        let rows = [
+            packed(|_| 3),
+            packed(|x| (x / 100) as u8 % 7),
+            packed(|x| (x * 7 / 13 % 7) as u8),
+            packed(|x| (x % 2) as u8),
+            packed(|x| if x == 0 || x == 599 { 4 } else { 1 }),
+            packed(|x| (x / 33 % 2) as u8 * 6),
+        ];
+        for row in rows {
+            let mut slot = [0u8; WIDTH as usize];
+            assert!(encode_row(&row, &mut slot, 1));
+            let mut decoded = [0u8; ROW_BYTES];
+            decode_row(&slot, &mut decoded);
+            assert_eq!(decoded, row);
+        }
+    }
+
+    #[test]
+    fn flat_rows_use_the_minimum_budget() {
+        let mut display = RleDisplay::<64>::default();
+        assert_eq!(display.used_bytes(), MIN_ROW_BUDGET * HEIGHT as usize);
+        display.clear_buffer(OctColor::Orange);
+        assert_eq!(encoded_len(&display.rows[0]), MIN_ROW_BUDGET);
+        assert_eq!(display.used_bytes(), MIN_ROW_BUDGET * HEIGHT as usize);
+    }
+
+    fn draw_stripes(
+        display: &mut RleDisplay<MIN_ROW_BUDGET>,
+    ) -> Result<(), RowBudgetExceededError> {
+        let stripes = (0..WIDTH as i32).map(|x| {
+            let color = if x % 2 == 0 {
+                OctColor::Red
+            } else {
+                OctColor::Blue
+            };
+            Pixel(Point::new(x, 5), color)
+        });
+        display.draw_iter(stripes)
+    }
+
+    #[test]
+    fn reject_keeps_the_row() {
+        let mut display = RleDisplay::<MIN_ROW_BUDGET>::default();
+        display.set_overflow_policy(RleOverflowPolicy::Reject);
+        // the error has the row on the panel, which is upside down by default
+        assert_eq!(
+            draw_stripes(&mut display),
+            Err(RowBudgetExceededError(HEIGHT - 1 - 5))
+        );
+        assert_eq!(display.degraded_rows(), 0);
+        for x in 0..WIDTH as i32 {
+            assert_eq!(display.pixel(Point::new(x, 5)), Some(OctColor::White));
+        }
+    }
+
+    #[test]
+    fn degrade_fits_the_row() {
+        let mut display = RleDisplay::<MIN_ROW_BUDGET>::default();
+        assert_eq!(draw_stripes(&mut display), Ok(()));
+        assert_eq!(display.degraded_rows(), 1);
+        // runs are absorbed from the panel's left, which is the right edge
+        assert_eq!(
+            display.pixel(Point::new(WIDTH as i32 - 1, 5)),
+            Some(OctColor::Blue)
+        );
+        assert!((0..WIDTH as i32).all(|x| {
+            matches!(
+                display.pixel(Point::new(x, 5)),
+                Some(OctColor::Red | OctColor::Blue)
+            )
+        }));
+        assert_eq!(display.used_bytes(), MIN_ROW_BUDGET * HEIGHT as usize);
+
+        display.reset_degraded_rows();
+        assert_eq!(display.degraded_rows(), 0);
+    }
+
+    #[test]
+    fn degrade_falls_back_to_a_single_color() {
+        // pairs of pixels only fit once everything is absorbed into the first color
+        let mut display = RleDisplay::<MIN_ROW_BUDGET>::default();
+        display.set_rotation(DisplayRotation::Rotate0);
+        let bands = (0..WIDTH as i32).map(|x| {
+            let color = if (x / 2) % 2 == 0 {
+                OctColor::Green
+            } else {
+                OctColor::Black
+            };
+            Pixel(Point::new(x, 0), color)
+        });
+        assert_eq!(display.draw_iter(bands), Ok(()));
+        assert_eq!(display.degraded_rows(), 1);
+        assert!((0..WIDTH as i32).all(|x| display.pixel(Point::new(x, 0)) == Some(OctColor::Green)));
+    }
+}
@@ -1,3 +1,5 @@
+use super::progress::{Progress, RefreshPhase};
+
 /// All commands need to have this trait which gives the address of the command
 /// which needs to be send via SPI with activated CommandsPin (Data/Command Pin in CommandMode)
 pub(crate) trait Command {
@@ -7,3 +9,35 @@ pub(crate) trait Command {
 pub trait IsBusy {
     fn is_busy(&mut self) -> bool;
 }
+
+/// A busy signal that reports when it can't be read
+///
+/// Used where guessing wrong is worse than waiting, e.g. before cutting power.
+pub trait TryIsBusy {
+    type Error;
+
+    fn try_is_busy(&mut self) -> Result<bool, Self::Error>;
+}
+
+/// Source of the packed pixel data that gets streamed to the panel
+///
+/// Lets buffers that don't store the frame in the panel's native format
+/// (two [`OctColor`](super::color::OctColor) nibbles per byte, row by row)
+/// expand it on the fly while it is sent.
+pub trait FrameSource {
+    /// Writes the packed panel bytes of the physical row `y` into `row`,
+    /// which is always `WIDTH / 2` bytes long
+    fn panel_row(&self, y: u32, row: &mut [u8]);
+}
+
+/// Gets called while the driver waits for the panel during a refresh
+///
+/// Lets the caller pulse LEDs, feed a watchdog or give up on the refresh.
+pub trait RefreshHook {
+    /// Current time in milliseconds from any fixed point, allowed to wrap around
+    fn now(&mut self) -> u32;
+
+    /// Called repeatedly while the panel is busy, with the milliseconds that
+    /// have passed since the refresh started
+    fn progress(&mut self, phase: RefreshPhase, elapsed: u32) -> Progress;
+}
@@ -0,0 +1,438 @@
+//! The front panel LEDs, driven by PWM
+//!
+//! Effects don't block: start one with [`Leds::set_effect`], then call
+//! [`Leds::tick`] with a millisecond timestamp every few milliseconds, e.g.
+//! while waiting for the display to refresh. Timestamps are allowed to wrap
+//! around.
+use embedded_hal::PwmPin;
+
+/// One of the LEDs on the front of the Inky Frame
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Led {
+    A,
+    B,
+    C,
+    D,
+    E,
+    Activity,
+    Connection,
+}
+
+impl Led {
+    /// All LEDs, in the order [`Leds::new`] takes their pins
+    pub const ALL: [Led; 7] = [
+        Led::A,
+        Led::B,
+        Led::C,
+        Led::D,
+        Led::E,
+        Led::Activity,
+        Led::Connection,
+    ];
+
+    const fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// An animation played on an LED, peaking at the LED's brightness
+///
+/// Periods are in milliseconds, a period of 0 leaves the LED off.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Effect {
+    /// On for the first half of every period, off for the second
+    Blink { period: u32 },
+    /// Fades up and back down linearly once per period
+    Pulse { period: u32 },
+    /// Fades up and back down smoothly once per period, easing in and out
+    Breathe { period: u32 },
+}
+
+impl Effect {
+    /// Level from 0 to 255 at `elapsed` milliseconds into the effect
+    pub const fn level(self, elapsed: u32) -> u8 {
+        let period = match self {
+            Effect::Blink { period } | Effect::Pulse { period } | Effect::Breathe { period } => {
+                period
+            }
+        };
+        if period == 0 {
+            return 0;
+        }
+        let phase = (elapsed % period) as u64;
+        let period = period as u64;
+        match self {
+            Effect::Blink { .. } => {
+                if phase * 2 < period {
+                    255
+                } else {
+                    0
+                }
+            }
+            Effect::Pulse { .. } => triangle(phase, period) as u8,
+            Effect::Breathe { .. } => {
+                // smoothstep, 3t² - 2t³
+                let t = triangle(phase, period);
+                (t * t * (3 * 255 - 2 * t) / (255 * 255)) as u8
+            }
+        }
+    }
+}
+
+/// Rises from 0 to 255 over the first half of the period, then falls back
+const fn triangle(phase: u64, period: u64) -> u64 {
+    let half = period.div_ceil(2);
+    if phase < half {
+        phase * 255 / half
+    } else {
+        (period - phase) * 255 / (period - half)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct LedState {
+    on: bool,
+    brightness: u8,
+    effect: Option<Effect>,
+    /// When the effect started, set by the first tick after it was
+    started: Option<u32>,
+}
+
+impl Default for LedState {
+    fn default() -> Self {
+        LedState {
+            on: false,
+            brightness: u8::MAX,
+            effect: None,
+            started: None,
+        }
+    }
+}
+
+/// The five button LEDs plus the activity and connection LEDs
+///
+/// Every LED can be on a different PWM slice, so each has its own pin type.
+/// Brightness goes from 0 to 255 and is squared before it is written as a
+/// duty cycle, so equal steps look roughly equally bright.
+pub struct Leds<A, B, C, D, E, Act, Conn> {
+    a: A,
+    b: B,
+    c: C,
+    d: D,
+    e: E,
+    activity: Act,
+    connection: Conn,
+    states: [LedState; 7],
+}
+
+impl<A, B, C, D, E, Act, Conn> Leds<A, B, C, D, E, Act, Conn>
+where
+    A: PwmPin<Duty = u16>,
+    B: PwmPin<Duty = u16>,
+    C: PwmPin<Duty = u16>,
+    D: PwmPin<Duty = u16>,
+    E: PwmPin<Duty = u16>,
+    Act: PwmPin<Duty = u16>,
+    Conn: PwmPin<Duty = u16>,
+{
+    /// Takes the pins in the order of [`Led::ALL`], all LEDs start off
+    pub fn new(a: A, b: B, c: C, d: D, e: E, activity: Act, connection: Conn) -> Self {
+        let mut leds = Leds {
+            a,
+            b,
+            c,
+            d,
+            e,
+            activity,
+            connection,
+            states: [LedState::default(); 7],
+        };
+        for led in Led::ALL {
+            let pin = leds.pin(led);
+            pin.set_duty(0);
+            pin.enable();
+        }
+        leds
+    }
+
+    /// Turns the LED on at its brightness, stopping any effect
+    pub fn on(&mut self, led: Led) {
+        self.set(led, true);
+    }
+
+    /// Turns the LED off, stopping any effect
+    pub fn off(&mut self, led: Led) {
+        self.set(led, false);
+    }
+
+    /// Turns the LED on or off, stopping any effect
+    pub fn set(&mut self, led: Led, on: bool) {
+        let state = &mut self.states[led.index()];
+        state.on = on;
+        state.effect = None;
+        self.write(led, if on { u8::MAX } else { 0 });
+    }
+
+    /// Turns every LED off and stops all effects
+    pub fn all_off(&mut self) {
+        for led in Led::ALL {
+            self.off(led);
+        }
+    }
+
+    /// Whether the LED is on or playing an effect
+    pub fn is_on(&self, led: Led) -> bool {
+        let state = &self.states[led.index()];
+        state.on || state.effect.is_some()
+    }
+
+    /// Sets how bright the LED is when on, and the peak of its effects
+    pub fn set_brightness(&mut self, led: Led, brightness: u8) {
+        let state = &mut self.states[led.index()];
+        state.brightness = brightness;
+        if state.effect.is_none() && state.on {
+            self.write(led, u8::MAX);
+        }
+    }
+
+    pub fn brightness(&self, led: Led) -> u8 {
+        self.states[led.index()].brightness
+    }
+
+    /// Starts an effect on the LED, it begins at the next [`tick`](Self::tick)
+    pub fn set_effect(&mut self, led: Led, effect: Effect) {
+        let state = &mut self.states[led.index()];
+        state.effect = Some(effect);
+        state.started = None;
+    }
+
+    pub fn effect(&self, led: Led) -> Option<Effect> {
+        self.states[led.index()].effect
+    }
+
+    /// Stops the LED's effect, leaving it on or off as it was before
+    pub fn stop_effect(&mut self, led: Led) {
+        let state = &mut self.states[led.index()];
+        state.effect = None;
+        let level = if state.on { u8::MAX } else { 0 };
+        self.write(led, level);
+    }
+
+    /// Advances the effects to `now`, in milliseconds
+    pub fn tick(&mut self, now: u32) {
+        for led in Led::ALL {
+            let state = &mut self.states[led.index()];
+            if let Some(effect) = state.effect {
+                let started = *state.started.get_or_insert(now);
+                let level = effect.level(now.wrapping_sub(started));
+                self.write(led, level);
+            }
+        }
+    }
+
+    /// Gives the pins back, in the order [`new`](Self::new) took them
+    pub fn release(self) -> (A, B, C, D, E, Act, Conn) {
+        (
+            self.a,
+            self.b,
+            self.c,
+            self.d,
+            self.e,
+            self.activity,
+            self.connection,
+        )
+    }
+
+    fn pin(&mut self, led: Led) -> &mut dyn PwmPin<Duty = u16> {
+        match led {
+            Led::A => &mut self.a,
+            Led::B => &mut self.b,
+            Led::C => &mut self.c,
+            Led::D => &mut self.d,
+            Led::E => &mut self.e,
+            Led::Activity => &mut self.activity,
+            Led::Connection => &mut self.connection,
+        }
+    }
+
+    /// Writes `level` scaled by the LED's brightness
+    fn write(&mut self, led: Led, level: u8) {
+        let brightness = self.states[led.index()].brightness as u32 * level as u32 / 255;
+        let pin = self.pin(led);
+        let duty = pin.get_max_duty() as u32 * brightness * brightness / (255 * 255);
+        pin.set_duty(duty as u16);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A PWM channel with its own counter top
+    #[derive(Default)]
+    struct Channel<const MAX: u16> {
+        duty: u16,
+        enabled: bool,
+    }
+
+    impl<const MAX: u16> PwmPin for Channel<MAX> {
+        type Duty = u16;
+
+        fn disable(&mut self) {
+            self.enabled = false;
+        }
+
+        fn enable(&mut self) {
+            self.enabled = true;
+        }
+
+        fn get_duty(&self) -> u16 {
+            self.duty
+        }
+
+        fn get_max_duty(&self) -> u16 {
+            MAX
+        }
+
+        fn set_duty(&mut self, duty: u16) {
+            self.duty = duty;
+        }
+    }
+
+    #[test]
+    fn drives_pins_of_different_types() {
+        let mut leds = Leds::new(
+            Channel::<1000>::default(),
+            Channel::<1000>::default(),
+            Channel::<1000>::default(),
+            Channel::<1000>::default(),
+            Channel::<1000>::default(),
+            Channel::<65535>::default(),
+            Channel::<255>::default(),
+        );
+        leds.on(Led::B);
+        leds.on(Led::Activity);
+        leds.set_brightness(Led::Connection, 128);
+        leds.on(Led::Connection);
+        let (a, b, _, _, _, activity, connection) = leds.release();
+        assert!(a.enabled && b.enabled && activity.enabled && connection.enabled);
+        assert_eq!(a.duty, 0);
+        assert_eq!(b.duty, 1000);
+        assert_eq!(activity.duty, 65535);
+        // brightness is squared, 128 is about a quarter
+        assert_eq!(connection.duty, 64);
+    }
+
+    // 255² so the duty is the level squared
+    type Square = Channel<65025>;
+
+    fn square_leds() -> Leds<Square, Square, Square, Square, Square, Square, Square> {
+        Leds::new(
+            Square::default(),
+            Square::default(),
+            Square::default(),
+            Square::default(),
+            Square::default(),
+            Square::default(),
+            Square::default(),
+        )
+    }
+
+    #[test]
+    fn blink_is_on_for_the_first_half() {
+        let blink = Effect::Blink { period: 100 };
+        assert_eq!(blink.level(0), 255);
+        assert_eq!(blink.level(49), 255);
+        assert_eq!(blink.level(50), 0);
+        assert_eq!(blink.level(99), 0);
+        assert_eq!(blink.level(100), 255);
+        assert_eq!(blink.level(1049), 255);
+        assert_eq!(Effect::Blink { period: 0 }.level(10), 0);
+    }
+
+    #[test]
+    fn pulse_is_a_triangle() {
+        let pulse = Effect::Pulse { period: 100 };
+        assert_eq!(pulse.level(0), 0);
+        assert_eq!(pulse.level(25), 127);
+        assert_eq!(pulse.level(50), 255);
+        assert_eq!(pulse.level(75), 127);
+        assert_eq!(pulse.level(100), 0);
+        // odd periods still reach the top
+        assert_eq!(Effect::Pulse { period: 7 }.level(4), 255);
+        assert_eq!(Effect::Pulse { period: 0 }.level(10), 0);
+    }
+
+    #[test]
+    fn breathe_eases_in_and_out() {
+        let breathe = Effect::Breathe { period: 100 };
+        let pulse = Effect::Pulse { period: 100 };
+        assert_eq!(breathe.level(0), 0);
+        assert_eq!(breathe.level(50), 255);
+        assert_eq!(breathe.level(100), 0);
+        assert!(breathe.level(25).abs_diff(127) <= 1);
+        // slower than the triangle near the ends, faster in the middle
+        assert!(breathe.level(10) < pulse.level(10));
+        assert!(breathe.level(40) > pulse.level(40));
+        assert!(breathe.level(90) < pulse.level(90));
+    }
+
+    #[test]
+    fn tick_plays_the_effect() {
+        let mut leds = square_leds();
+        leds.set_effect(Led::C, Effect::Blink { period: 100 });
+        assert!(leds.is_on(Led::C));
+        // the effect starts at the first tick
+        leds.tick(1000);
+        assert_eq!(leds.c.duty, 65025);
+        leds.tick(1050);
+        assert_eq!(leds.c.duty, 0);
+        leds.tick(1100);
+        assert_eq!(leds.c.duty, 65025);
+
+        leds.set_effect(Led::C, Effect::Pulse { period: 100 });
+        leds.set_brightness(Led::C, 128);
+        leds.tick(2000);
+        assert_eq!(leds.c.duty, 0);
+        leds.tick(2050);
+        assert_eq!(leds.c.duty, 128 * 128);
+        // other LEDs are left alone
+        assert_eq!(leds.a.duty, 0);
+    }
+
+    #[test]
+    fn tick_handles_wraparound() {
+        let mut leds = square_leds();
+        leds.set_effect(Led::A, Effect::Blink { period: 100 });
+        leds.tick(u32::MAX - 9);
+        leds.tick(40);
+        assert_eq!(leds.a.duty, 0);
+        leds.tick(90);
+        assert_eq!(leds.a.duty, 65025);
+    }
+
+    #[test]
+    fn stopping_restores_the_steady_level() {
+        let mut leds = square_leds();
+        leds.on(Led::Activity);
+        leds.set_effect(Led::Activity, Effect::Blink { period: 100 });
+        leds.tick(0);
+        leds.tick(60);
+        assert_eq!(leds.activity.duty, 0);
+        leds.stop_effect(Led::Activity);
+        assert_eq!(leds.effect(Led::Activity), None);
+        assert_eq!(leds.activity.duty, 65025);
+        leds.tick(100);
+        assert_eq!(leds.activity.duty, 65025);
+
+        leds.set_effect(Led::E, Effect::Pulse { period: 100 });
+        leds.tick(0);
+        leds.tick(50);
+        assert_eq!(leds.e.duty, 65025);
+        leds.stop_effect(Led::E);
+        assert_eq!(leds.e.duty, 0);
+        assert!(!leds.is_on(Led::E));
+    }
+}
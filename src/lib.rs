@@ -3,6 +3,13 @@
 #[cfg(feature = "display")]
 pub mod display;
 
+pub mod battery;
+pub mod buttons;
+pub mod leds;
+pub mod piso;
+pub mod power;
+pub mod rtc;
+pub mod schedule;
 pub mod shift_register;
 
 pub use display::InkyFrameDisplay;
@@ -0,0 +1,313 @@
+use embedded_hal::blocking::delay::DelayUs;
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+
+/// Order in which bits are shifted out of each register
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum BitOrder {
+    /// The first bit shifted out is bit 7 of the byte, input H on a 74HC165
+    #[default]
+    MsbFirst,
+    /// The first bit shifted out is bit 0 of the byte
+    LsbFirst,
+}
+
+/// Delay provider that doesn't wait, for when the GPIO is slow enough as is
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct NoDelay;
+
+impl DelayUs<u32> for NoDelay {
+    fn delay_us(&mut self, _us: u32) {}
+}
+
+/// Driver for a chain of 74HC165 style parallel-in serial-out shift registers
+///
+/// `latch_pin` is the active low parallel load (PL), `clock_pin` the clock
+/// (CP) and `data_pin` the serial output (Q7) of the register nearest to the
+/// microcontroller. The clock inhibit (CE) can be tied low or handed over with
+/// [`with_inhibit`](Self::with_inhibit).
+pub struct ParallelInShiftRegister<GpioOutput, GpioInput, D = NoDelay> {
+    clock_pin: GpioOutput,
+    latch_pin: GpioOutput,
+    data_pin: GpioInput,
+    inhibit_pin: Option<GpioOutput>,
+    delay: D,
+    edge_delay_us: u32,
+    bit_order: BitOrder,
+}
+
+impl<GpioOutput, GpioInput> ParallelInShiftRegister<GpioOutput, GpioInput> {
+    pub fn new(clock_pin: GpioOutput, latch_pin: GpioOutput, data_pin: GpioInput) -> Self {
+        ParallelInShiftRegister {
+            clock_pin,
+            latch_pin,
+            data_pin,
+            inhibit_pin: None,
+            delay: NoDelay,
+            edge_delay_us: 0,
+            bit_order: BitOrder::default(),
+        }
+    }
+}
+
+impl<GpioOutput, GpioInput, D, GpioE> ParallelInShiftRegister<GpioOutput, GpioInput, D>
+where
+    GpioOutput: OutputPin<Error = GpioE>,
+{
+    /// Drives the clock inhibit pin, which is set high right away and held
+    /// high between reads
+    pub fn with_inhibit(mut self, mut inhibit_pin: GpioOutput) -> Result<Self, GpioE> {
+        inhibit_pin.set_high()?;
+        self.inhibit_pin = Some(inhibit_pin);
+        Ok(self)
+    }
+}
+
+impl<GpioOutput, GpioInput, D> ParallelInShiftRegister<GpioOutput, GpioInput, D> {
+    /// Waits `edge_delay_us` after every edge, for long or slow chains
+    pub fn with_delay<D2>(
+        self,
+        delay: D2,
+        edge_delay_us: u32,
+    ) -> ParallelInShiftRegister<GpioOutput, GpioInput, D2> {
+        ParallelInShiftRegister {
+            clock_pin: self.clock_pin,
+            latch_pin: self.latch_pin,
+            data_pin: self.data_pin,
+            inhibit_pin: self.inhibit_pin,
+            delay,
+            edge_delay_us,
+            bit_order: self.bit_order,
+        }
+    }
+
+    pub fn with_bit_order(mut self, bit_order: BitOrder) -> Self {
+        self.bit_order = bit_order;
+        self
+    }
+
+    pub fn set_bit_order(&mut self, bit_order: BitOrder) {
+        self.bit_order = bit_order;
+    }
+
+    pub fn bit_order(&self) -> BitOrder {
+        self.bit_order
+    }
+}
+
+impl<GpioOutput, GpioInput, D, GpioE> ParallelInShiftRegister<GpioOutput, GpioInput, D>
+where
+    GpioOutput: OutputPin<Error = GpioE>,
+    GpioInput: InputPin<Error = GpioE>,
+    D: DelayUs<u32>,
+{
+    /// Latches the inputs and shifts `N` bytes out of the chain
+    ///
+    /// The first byte comes from the register nearest to the microcontroller.
+    pub fn read<const N: usize>(&mut self) -> Result<[u8; N], GpioE> {
+        let mut bytes = [0u8; N];
+        self.read_into(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Latches the inputs and shifts one byte out
+    pub fn read_byte(&mut self) -> Result<u8, GpioE> {
+        Ok(self.read::<1>()?[0])
+    }
+
+    /// Latches the inputs and fills `bytes` from the chain, see [`read`](Self::read)
+    pub fn read_into(&mut self, bytes: &mut [u8]) -> Result<(), GpioE> {
+        self.latch_pin.set_low()?;
+        self.wait();
+        self.latch_pin.set_high()?;
+        self.wait();
+        if let Some(inhibit_pin) = &mut self.inhibit_pin {
+            inhibit_pin.set_low()?;
+            self.wait();
+        }
+
+        let result = self.shift_in(bytes);
+
+        if let Some(inhibit_pin) = &mut self.inhibit_pin {
+            inhibit_pin.set_high()?;
+        }
+        result
+    }
+
+    fn shift_in(&mut self, bytes: &mut [u8]) -> Result<(), GpioE> {
+        for byte in bytes.iter_mut() {
+            *byte = 0;
+            for bit in 0..8 {
+                if self.data_pin.is_high()? {
+                    *byte |= match self.bit_order {
+                        BitOrder::MsbFirst => 0x80 >> bit,
+                        BitOrder::LsbFirst => 1 << bit,
+                    };
+                }
+                self.clock_pin.set_low()?;
+                self.wait();
+                self.clock_pin.set_high()?;
+                self.wait();
+            }
+        }
+        Ok(())
+    }
+
+    fn wait(&mut self) {
+        if self.edge_delay_us > 0 {
+            self.delay.delay_us(self.edge_delay_us);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::Cell;
+    use core::convert::Infallible;
+
+    /// Up to four 74HC165s in a chain, `inputs` holds the parallel inputs
+    /// with the register nearest to the microcontroller in the top byte
+    #[derive(Default)]
+    struct Chain {
+        inputs: Cell<u32>,
+        shift: Cell<u32>,
+        clock: Cell<bool>,
+        inhibit: Cell<bool>,
+        delays: Cell<u32>,
+        delayed_us: Cell<u32>,
+    }
+
+    enum Role {
+        Clock,
+        Latch,
+        Inhibit,
+    }
+
+    struct Output<'a>(&'a Chain, Role);
+
+    impl OutputPin for Output<'_> {
+        type Error = Infallible;
+
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            match self.1 {
+                Role::Clock => self.0.clock.set(false),
+                Role::Latch => self.0.shift.set(self.0.inputs.get()),
+                Role::Inhibit => self.0.inhibit.set(false),
+            }
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            match self.1 {
+                Role::Clock => {
+                    // a rising edge only shifts while the clock isn't inhibited
+                    if !self.0.clock.get() && !self.0.inhibit.get() {
+                        self.0.shift.set(self.0.shift.get() << 1);
+                    }
+                    self.0.clock.set(true);
+                }
+                Role::Latch => {}
+                Role::Inhibit => self.0.inhibit.set(true),
+            }
+            Ok(())
+        }
+    }
+
+    struct Data<'a>(&'a Chain);
+
+    impl InputPin for Data<'_> {
+        type Error = Infallible;
+
+        fn is_high(&self) -> Result<bool, Infallible> {
+            Ok(self.0.shift.get() & 0x8000_0000 != 0)
+        }
+
+        fn is_low(&self) -> Result<bool, Infallible> {
+            self.is_high().map(|high| !high)
+        }
+    }
+
+    struct Delay<'a>(&'a Chain);
+
+    impl DelayUs<u32> for Delay<'_> {
+        fn delay_us(&mut self, us: u32) {
+            self.0.delays.set(self.0.delays.get() + 1);
+            self.0.delayed_us.set(self.0.delayed_us.get() + us);
+        }
+    }
+
+    fn register(chain: &Chain) -> ParallelInShiftRegister<Output<'_>, Data<'_>> {
+        ParallelInShiftRegister::new(
+            Output(chain, Role::Clock),
+            Output(chain, Role::Latch),
+            Data(chain),
+        )
+    }
+
+    #[test]
+    fn daisy_chain_starts_with_the_nearest_register() {
+        let chain = Chain::default();
+        chain.inputs.set(0x12_34_56_78);
+        let mut register = register(&chain);
+        assert_eq!(register.read::<4>(), Ok([0x12, 0x34, 0x56, 0x78]));
+        assert_eq!(register.read::<2>(), Ok([0x12, 0x34]));
+        assert_eq!(register.read_byte(), Ok(0x12));
+    }
+
+    #[test]
+    fn lsb_first_reverses_each_byte() {
+        let chain = Chain::default();
+        chain.inputs.set(0b1000_0011_0100_0000 << 16);
+        let mut register = register(&chain).with_bit_order(BitOrder::LsbFirst);
+        assert_eq!(register.bit_order(), BitOrder::LsbFirst);
+        assert_eq!(register.read::<2>(), Ok([0b1100_0001, 0b0000_0010]));
+        register.set_bit_order(BitOrder::MsbFirst);
+        assert_eq!(register.read::<2>(), Ok([0b1000_0011, 0b0100_0000]));
+    }
+
+    #[test]
+    fn inhibit_is_only_low_while_shifting() {
+        let chain = Chain::default();
+        chain.inputs.set(0xa5 << 24);
+        let mut register = register(&chain)
+            .with_inhibit(Output(&chain, Role::Inhibit))
+            .unwrap();
+        assert!(chain.inhibit.get());
+        assert_eq!(register.read_byte(), Ok(0xa5));
+        assert!(chain.inhibit.get());
+
+        // held high, the clock can't shift the chain
+        chain.shift.set(0x8000_0000);
+        Output(&chain, Role::Clock).set_low().unwrap();
+        Output(&chain, Role::Clock).set_high().unwrap();
+        assert_eq!(chain.shift.get(), 0x8000_0000);
+    }
+
+    #[test]
+    fn waits_after_every_edge() {
+        let chain = Chain::default();
+        chain.inputs.set(0xff_00 << 16);
+        let mut register = register(&chain).with_delay(Delay(&chain), 5);
+        assert_eq!(register.read::<2>(), Ok([0xff, 0x00]));
+        // latch low and high, then both clock edges for every bit
+        assert_eq!(chain.delays.get(), 2 + 2 * 16);
+        assert_eq!(chain.delayed_us.get(), 5 * (2 + 2 * 16));
+
+        // the inhibit pin adds one more
+        let mut register = register
+            .with_inhibit(Output(&chain, Role::Inhibit))
+            .unwrap();
+        chain.delays.set(0);
+        assert_eq!(register.read_byte(), Ok(0xff));
+        assert_eq!(chain.delays.get(), 3 + 2 * 8);
+    }
+
+    #[test]
+    fn no_edge_delay_skips_the_delay() {
+        let chain = Chain::default();
+        let mut register = register(&chain).with_delay(Delay(&chain), 0);
+        register.read::<2>().unwrap();
+        assert_eq!(chain.delays.get(), 0);
+    }
+}
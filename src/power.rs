@@ -0,0 +1,138 @@
+//! Keeping the Inky Frame powered while it runs from battery
+//!
+//! On battery the board only stays on while the VSYS hold pin is driven high.
+//! Hold it as early as possible at boot, right after reading the
+//! [wake reason](crate::shift_register::InkyFrameShiftRegister::wake_reason),
+//! and release it to power off until the next button press, RTC alarm or
+//! external trigger. On USB power releasing it does nothing.
+use embedded_hal::digital::v2::OutputPin;
+
+use crate::display::TryIsBusy;
+
+/// When the board can't be shut down
+#[derive(Debug, PartialEq, Eq)]
+pub enum ShutdownError<E> {
+    /// The display is still busy, or its busy signal couldn't be read,
+    /// cutting power now could damage it
+    DisplayBusy,
+    /// Driving the hold pin failed
+    Pin(E),
+}
+
+impl<E: core::fmt::Debug> core::fmt::Display for ShutdownError<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ShutdownError::DisplayBusy => write!(f, "Can't shut down while the display is busy"),
+            ShutdownError::Pin(error) => write!(f, "Failed to drive the hold pin: {:?}", error),
+        }
+    }
+}
+
+/// Owner of the VSYS hold pin
+pub struct PowerControl<GpioOutput> {
+    hold_pin: GpioOutput,
+    held: bool,
+}
+
+impl<GpioOutput, GpioE> PowerControl<GpioOutput>
+where
+    GpioOutput: OutputPin<Error = GpioE>,
+{
+    /// Takes the hold pin without driving it, call [`hold`](Self::hold) next
+    pub fn new(hold_pin: GpioOutput) -> Self {
+        PowerControl {
+            hold_pin,
+            held: false,
+        }
+    }
+
+    /// Keeps the board powered on battery
+    pub fn hold(&mut self) -> Result<(), GpioE> {
+        self.hold_pin.set_high()?;
+        self.held = true;
+        Ok(())
+    }
+
+    /// Stops holding power, on battery the board turns off straight away
+    ///
+    /// Doesn't check the display, see [`shutdown_until_wake`](Self::shutdown_until_wake).
+    pub fn release(&mut self) -> Result<(), GpioE> {
+        self.hold_pin.set_low()?;
+        self.held = false;
+        Ok(())
+    }
+
+    /// Whether the hold pin is being driven high
+    pub fn is_held(&self) -> bool {
+        self.held
+    }
+
+    /// Powers the board off until it is woken up again, unless the display
+    /// is still busy with a refresh or its busy signal can't be read
+    ///
+    /// On battery the board loses power before this returns. On USB power it
+    /// returns `Ok` and the board keeps running, so the caller has to carry
+    /// on, e.g. loop back to waiting for the next wake, or halt.
+    pub fn shutdown_until_wake(
+        &mut self,
+        busy_signal: &mut impl TryIsBusy,
+    ) -> Result<(), ShutdownError<GpioE>> {
+        if busy_signal.try_is_busy().unwrap_or(true) {
+            return Err(ShutdownError::DisplayBusy);
+        }
+        self.release().map_err(ShutdownError::Pin)
+    }
+
+    /// Gives the hold pin back
+    pub fn release_pin(self) -> GpioOutput {
+        self.hold_pin
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::convert::Infallible;
+
+    struct HoldPin(Option<bool>);
+
+    impl OutputPin for HoldPin {
+        type Error = Infallible;
+
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            self.0 = Some(false);
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            self.0 = Some(true);
+            Ok(())
+        }
+    }
+
+    struct Busy(Result<bool, ()>);
+
+    impl TryIsBusy for Busy {
+        type Error = ();
+
+        fn try_is_busy(&mut self) -> Result<bool, ()> {
+            self.0
+        }
+    }
+
+    #[test]
+    fn only_shuts_down_when_the_display_is_idle() {
+        for (busy, shuts_down) in [(Ok(false), true), (Ok(true), false), (Err(()), false)] {
+            let mut power = PowerControl::new(HoldPin(None));
+            power.hold().unwrap();
+            let result = power.shutdown_until_wake(&mut Busy(busy));
+            if shuts_down {
+                assert_eq!(result, Ok(()));
+            } else {
+                assert_eq!(result, Err(ShutdownError::DisplayBusy), "{busy:?}");
+            }
+            assert_eq!(power.is_held(), !shuts_down);
+            assert_eq!(power.release_pin().0, Some(!shuts_down));
+        }
+    }
+}
@@ -0,0 +1,650 @@
+//! Driver for the PCF85063A real-time clock that wakes the Inky Frame
+//!
+//! The RTC's interrupt line is latched onto the shift register, so after a
+//! wake [`Inputs::rtc_alarm`] tells whether it fired and
+//! [`Pcf85063a::acknowledge`] clears it again. The clock is always run in 24
+//! hour mode.
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+
+use crate::shift_register::Inputs;
+
+/// I2C address of the PCF85063A
+pub const ADDRESS: u8 = 0x51;
+
+const CONTROL_1: u8 = 0x00;
+const CONTROL_2: u8 = 0x01;
+const SECONDS: u8 = 0x04;
+const SECOND_ALARM: u8 = 0x0B;
+const TIMER_VALUE: u8 = 0x10;
+const TIMER_MODE: u8 = 0x11;
+
+const SOFTWARE_RESET: u8 = 0x58;
+const HOUR_MODE_12: u8 = 1 << 1;
+const OSCILLATOR_STOPPED: u8 = 1 << 7;
+const ALARM_DISABLED: u8 = 1 << 7;
+
+const ALARM_INTERRUPT: u8 = 1 << 7;
+const ALARM_FLAG: u8 = 1 << 6;
+const TIMER_FLAG: u8 = 1 << 3;
+const CLOCK_OUTPUT_MASK: u8 = 0b111;
+
+const TIMER_ENABLE: u8 = 1 << 2;
+const TIMER_INTERRUPT: u8 = 1 << 1;
+
+/// When talking to the RTC fails
+#[derive(Debug, PartialEq, Eq)]
+pub enum RtcError<E> {
+    /// The I2C bus returned an error
+    I2c(E),
+    /// The date and time doesn't exist or is outside of 2000 to 2099
+    InvalidDateTime,
+    /// A field of the alarm is out of range
+    InvalidAlarm,
+}
+
+impl<E: core::fmt::Debug> core::fmt::Display for RtcError<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            RtcError::I2c(error) => write!(f, "I2C error talking to the RTC: {:?}", error),
+            RtcError::InvalidDateTime => write!(f, "Date and time can't be stored by the RTC"),
+            RtcError::InvalidAlarm => write!(f, "Alarm field is out of range"),
+        }
+    }
+}
+
+/// Day of the week, numbered the way the RTC counts them
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Weekday {
+    Sunday,
+    Monday,
+    Tuesday,
+    Wednesday,
+    Thursday,
+    Friday,
+    Saturday,
+}
+
+impl Weekday {
+    /// All days starting from Sunday
+    pub const ALL: [Weekday; 7] = [
+        Weekday::Sunday,
+        Weekday::Monday,
+        Weekday::Tuesday,
+        Weekday::Wednesday,
+        Weekday::Thursday,
+        Weekday::Friday,
+        Weekday::Saturday,
+    ];
+
+    /// The day from its number, 0 is Sunday, wrapping after Saturday
+    pub const fn from_index(index: u8) -> Weekday {
+        Weekday::ALL[index as usize % 7]
+    }
+
+    /// Number of the day, 0 is Sunday
+    pub const fn index(self) -> u8 {
+        self as u8
+    }
+}
+
+/// A date and time in 24 hour format between 2000 and 2099
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct DateTime {
+    pub year: u16,
+    /// 1 to 12
+    pub month: u8,
+    /// 1 to 31
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    pub const fn new(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> Self {
+        DateTime {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+        }
+    }
+
+    /// Whether the RTC can store this date and time
+    pub const fn is_valid(&self) -> bool {
+        self.year >= 2000
+            && self.year <= 2099
+            && self.month >= 1
+            && self.month <= 12
+            && self.day >= 1
+            && self.day <= days_in_month(self.year, self.month)
+            && self.hour < 24
+            && self.minute < 60
+            && self.second < 60
+    }
+
+    /// Day of the week of the date
+    ///
+    /// Doesn't panic on dates that aren't [valid](Self::is_valid), but the
+    /// day it gives for them means nothing.
+    pub const fn weekday(&self) -> Weekday {
+        // Sakamoto's method
+        const OFFSETS: [u32; 12] = [0, 3, 2, 5, 0, 3, 5, 1, 4, 6, 2, 4];
+        let year = if self.month < 3 {
+            self.year.saturating_sub(1)
+        } else {
+            self.year
+        } as u32;
+        let day = year + year / 4 - year / 100
+            + year / 400
+            + OFFSETS[self.month.wrapping_sub(1) as usize % 12]
+            + self.day as u32;
+        Weekday::from_index((day % 7) as u8)
+    }
+}
+
+/// Number of days in the month of the year, 0 for a month that doesn't exist
+pub const fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400)) => {
+            29
+        }
+        2 => 28,
+        _ => 0,
+    }
+}
+
+/// When the alarm goes off, fields left as `None` match any value
+///
+/// The alarm fires when every set field matches the clock, e.g. only setting
+/// `minute` to 30 fires at half past every hour.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Alarm {
+    pub second: Option<u8>,
+    pub minute: Option<u8>,
+    pub hour: Option<u8>,
+    /// Day of the month
+    pub day: Option<u8>,
+    pub weekday: Option<Weekday>,
+}
+
+impl Alarm {
+    /// Alarm that fires once a day at this time
+    pub const fn daily(hour: u8, minute: u8, second: u8) -> Self {
+        Alarm {
+            second: Some(second),
+            minute: Some(minute),
+            hour: Some(hour),
+            day: None,
+            weekday: None,
+        }
+    }
+
+    /// Alarm that fires at this date and time, once a month
+    pub const fn at(time: &DateTime) -> Self {
+        Alarm {
+            second: Some(time.second),
+            minute: Some(time.minute),
+            hour: Some(time.hour),
+            day: Some(time.day),
+            weekday: None,
+        }
+    }
+
+    const fn is_valid(&self) -> bool {
+        matches!(self.second, None | Some(0..=59))
+            && matches!(self.minute, None | Some(0..=59))
+            && matches!(self.hour, None | Some(0..=23))
+            && matches!(self.day, None | Some(1..=31))
+    }
+}
+
+/// Source clock of the countdown timer, it counts down once per tick
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TimerClock {
+    Hz4096 = 0b00,
+    Hz64 = 0b01,
+    Hz1 = 0b10,
+    /// One tick per minute
+    PerMinute = 0b11,
+}
+
+/// Frequency of the CLKOUT pin
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ClockOutput {
+    /// The chip's reset value
+    #[default]
+    Hz32768 = 0b000,
+    Hz16384 = 0b001,
+    Hz8192 = 0b010,
+    Hz4096 = 0b011,
+    Hz2048 = 0b100,
+    Hz1024 = 0b101,
+    Hz1 = 0b110,
+    /// CLKOUT is held low, saving power
+    Off = 0b111,
+}
+
+/// PCF85063A real-time clock on an I2C bus
+pub struct Pcf85063a<I2C> {
+    i2c: I2C,
+}
+
+impl<I2C, E> Pcf85063a<I2C>
+where
+    I2C: Write<Error = E> + WriteRead<Error = E>,
+{
+    pub fn new(i2c: I2C) -> Self {
+        Pcf85063a { i2c }
+    }
+
+    /// Gives the bus back
+    pub fn release(self) -> I2C {
+        self.i2c
+    }
+
+    /// Resets every register to its default, stopping alarms and the timer
+    pub fn reset(&mut self) -> Result<(), RtcError<E>> {
+        self.write(CONTROL_1, &[SOFTWARE_RESET])
+    }
+
+    /// Reads the current date and time
+    pub fn time(&mut self) -> Result<DateTime, RtcError<E>> {
+        let mut registers = [0u8; 7];
+        self.read(SECONDS, &mut registers)?;
+        Ok(DateTime {
+            second: from_bcd(registers[0] & 0x7F),
+            minute: from_bcd(registers[1] & 0x7F),
+            hour: from_bcd(registers[2] & 0x3F),
+            day: from_bcd(registers[3] & 0x3F),
+            month: from_bcd(registers[5] & 0x1F),
+            year: 2000 + from_bcd(registers[6]) as u16,
+        })
+    }
+
+    /// Sets the date and time, which also clears [`oscillator_stopped`](Self::oscillator_stopped)
+    pub fn set_time(&mut self, time: &DateTime) -> Result<(), RtcError<E>> {
+        if !time.is_valid() {
+            return Err(RtcError::InvalidDateTime);
+        }
+        let control = self.read_register(CONTROL_1)?;
+        if control & HOUR_MODE_12 != 0 {
+            self.write(CONTROL_1, &[control & !HOUR_MODE_12])?;
+        }
+        self.write(
+            SECONDS,
+            &[
+                to_bcd(time.second),
+                to_bcd(time.minute),
+                to_bcd(time.hour),
+                to_bcd(time.day),
+                time.weekday().index(),
+                to_bcd(time.month),
+                to_bcd((time.year - 2000) as u8),
+            ],
+        )
+    }
+
+    /// Whether the oscillator has stopped since the time was last set, e.g.
+    /// because the board lost all power, so the time can't be trusted
+    pub fn oscillator_stopped(&mut self) -> Result<bool, RtcError<E>> {
+        Ok(self.read_register(SECONDS)? & OSCILLATOR_STOPPED != 0)
+    }
+
+    /// Sets the alarm and enables its interrupt, which wakes the board
+    pub fn set_alarm(&mut self, alarm: &Alarm) -> Result<(), RtcError<E>> {
+        if !alarm.is_valid() {
+            return Err(RtcError::InvalidAlarm);
+        }
+        let field = |value: Option<u8>| match value {
+            Some(value) => to_bcd(value),
+            None => ALARM_DISABLED,
+        };
+        self.write(
+            SECOND_ALARM,
+            &[
+                field(alarm.second),
+                field(alarm.minute),
+                field(alarm.hour),
+                field(alarm.day),
+                match alarm.weekday {
+                    Some(weekday) => weekday.index(),
+                    None => ALARM_DISABLED,
+                },
+            ],
+        )?;
+        self.clear_alarm_flag()?;
+        self.update_control_2(|control| control | ALARM_INTERRUPT)
+    }
+
+    /// Turns the alarm and its interrupt off
+    pub fn disable_alarm(&mut self) -> Result<(), RtcError<E>> {
+        self.write(SECOND_ALARM, &[ALARM_DISABLED; 5])?;
+        self.update_control_2(|control| control & !ALARM_INTERRUPT)
+    }
+
+    /// Whether the alarm has gone off since its flag was last cleared
+    pub fn alarm_triggered(&mut self) -> Result<bool, RtcError<E>> {
+        Ok(self.read_register(CONTROL_2)? & ALARM_FLAG != 0)
+    }
+
+    /// Clears the alarm flag, releasing the interrupt line
+    pub fn clear_alarm_flag(&mut self) -> Result<(), RtcError<E>> {
+        self.update_control_2(|control| control & !ALARM_FLAG)
+    }
+
+    /// Starts the countdown timer with its interrupt, it goes off after
+    /// `ticks` ticks of `clock`
+    pub fn set_timer(&mut self, ticks: u8, clock: TimerClock) -> Result<(), RtcError<E>> {
+        self.write(TIMER_MODE, &[0])?;
+        self.clear_timer_flag()?;
+        self.write(
+            TIMER_VALUE,
+            &[ticks, (clock as u8) << 3 | TIMER_ENABLE | TIMER_INTERRUPT],
+        )
+    }
+
+    /// Stops the countdown timer and its interrupt
+    pub fn disable_timer(&mut self) -> Result<(), RtcError<E>> {
+        self.write(TIMER_MODE, &[0])
+    }
+
+    /// Ticks left on the countdown timer
+    pub fn timer_value(&mut self) -> Result<u8, RtcError<E>> {
+        self.read_register(TIMER_VALUE)
+    }
+
+    /// Whether the countdown timer has run out since its flag was last cleared
+    pub fn timer_triggered(&mut self) -> Result<bool, RtcError<E>> {
+        Ok(self.read_register(CONTROL_2)? & TIMER_FLAG != 0)
+    }
+
+    /// Clears the timer flag, releasing the interrupt line
+    pub fn clear_timer_flag(&mut self) -> Result<(), RtcError<E>> {
+        self.update_control_2(|control| control & !TIMER_FLAG)
+    }
+
+    /// Sets the frequency of the CLKOUT pin
+    pub fn set_clock_output(&mut self, output: ClockOutput) -> Result<(), RtcError<E>> {
+        self.update_control_2(|control| control & !CLOCK_OUTPUT_MASK | output as u8)
+    }
+
+    /// Clears the alarm and timer flags when the shift register shows the
+    /// RTC interrupt, returning whether it did
+    ///
+    /// The interrupt line stays asserted until then, so the board would wake
+    /// straight back up after the next shutdown.
+    pub fn acknowledge(&mut self, inputs: Inputs) -> Result<bool, RtcError<E>> {
+        if !inputs.rtc_alarm() {
+            return Ok(false);
+        }
+        self.update_control_2(|control| control & !(ALARM_FLAG | TIMER_FLAG))?;
+        Ok(true)
+    }
+
+    /// Read-modify-write of Control_2
+    ///
+    /// Writing a 0 clears the alarm and timer flags, so they are written as 1
+    /// unless `update` clears them on purpose.
+    fn update_control_2(&mut self, update: impl FnOnce(u8) -> u8) -> Result<(), RtcError<E>> {
+        let control = self.read_register(CONTROL_2)?;
+        self.write(CONTROL_2, &[update(control | ALARM_FLAG | TIMER_FLAG)])
+    }
+
+    fn read_register(&mut self, register: u8) -> Result<u8, RtcError<E>> {
+        let mut value = [0u8];
+        self.read(register, &mut value)?;
+        Ok(value[0])
+    }
+
+    fn read(&mut self, register: u8, buffer: &mut [u8]) -> Result<(), RtcError<E>> {
+        self.i2c
+            .write_read(ADDRESS, &[register], buffer)
+            .map_err(RtcError::I2c)
+    }
+
+    fn write(&mut self, register: u8, data: &[u8]) -> Result<(), RtcError<E>> {
+        let mut buffer = [0u8; 8];
+        buffer[0] = register;
+        buffer[1..=data.len()].copy_from_slice(data);
+        self.i2c
+            .write(ADDRESS, &buffer[..=data.len()])
+            .map_err(RtcError::I2c)
+    }
+}
+
+const fn to_bcd(value: u8) -> u8 {
+    ((value / 10) << 4) | (value % 10)
+}
+
+const fn from_bcd(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0x0F)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::convert::Infallible;
+
+    /// Registers of a PCF85063A, recording every write
+    struct Bus {
+        registers: [u8; 0x12],
+        writes: [([u8; 8], usize); 16],
+        write_count: usize,
+        reads: [u8; 16],
+        read_count: usize,
+    }
+
+    impl Bus {
+        fn new(registers: &[(u8, u8)]) -> Self {
+            let mut bus = Bus {
+                registers: [0; 0x12],
+                writes: [([0; 8], 0); 16],
+                write_count: 0,
+                reads: [0; 16],
+                read_count: 0,
+            };
+            for &(register, value) in registers {
+                bus.registers[register as usize] = value;
+            }
+            bus
+        }
+
+        fn writes(&self) -> impl Iterator<Item = &[u8]> {
+            self.writes[..self.write_count]
+                .iter()
+                .map(|(bytes, len)| &bytes[..*len])
+        }
+
+        fn reads(&self) -> &[u8] {
+            &self.reads[..self.read_count]
+        }
+    }
+
+    impl Write for Bus {
+        type Error = Infallible;
+
+        fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Infallible> {
+            assert_eq!(address, ADDRESS);
+            let write = &mut self.writes[self.write_count];
+            write.0[..bytes.len()].copy_from_slice(bytes);
+            write.1 = bytes.len();
+            self.write_count += 1;
+            for (i, &value) in bytes[1..].iter().enumerate() {
+                let register = bytes[0] as usize + i;
+                self.registers[register] = if register == CONTROL_2 as usize {
+                    // the flags can only be cleared, writing a 1 keeps them
+                    let flags = ALARM_FLAG | TIMER_FLAG;
+                    value & !flags | value & self.registers[register] & flags
+                } else {
+                    value
+                };
+            }
+            Ok(())
+        }
+    }
+
+    impl WriteRead for Bus {
+        type Error = Infallible;
+
+        fn write_read(
+            &mut self,
+            address: u8,
+            bytes: &[u8],
+            buffer: &mut [u8],
+        ) -> Result<(), Infallible> {
+            assert_eq!(address, ADDRESS);
+            assert_eq!(bytes.len(), 1);
+            self.reads[self.read_count] = bytes[0];
+            self.read_count += 1;
+            let start = bytes[0] as usize;
+            buffer.copy_from_slice(&self.registers[start..start + buffer.len()]);
+            Ok(())
+        }
+    }
+
+    /// Both flags raised and CLKOUT at 1024 Hz
+    const RAISED: u8 = ALARM_FLAG | TIMER_FLAG | ClockOutput::Hz1024 as u8;
+
+    #[test]
+    fn sets_and_reads_the_time() {
+        let mut rtc = Pcf85063a::new(Bus::new(&[(CONTROL_1, HOUR_MODE_12 | 1)]));
+        let time = DateTime::new(2024, 2, 29, 13, 45, 7);
+        rtc.set_time(&time).unwrap();
+        assert_eq!(rtc.time(), Ok(time));
+
+        let bus = rtc.release();
+        assert_eq!(bus.reads(), &[CONTROL_1, SECONDS]);
+        let mut writes = bus.writes();
+        assert_eq!(writes.next(), Some(&[CONTROL_1, 1][..]));
+        // Thursday
+        assert_eq!(
+            writes.next(),
+            Some(&[SECONDS, 0x07, 0x45, 0x13, 0x29, 4, 0x02, 0x24][..])
+        );
+        assert_eq!(writes.next(), None);
+    }
+
+    #[test]
+    fn rejects_invalid_times() {
+        let mut rtc = Pcf85063a::new(Bus::new(&[]));
+        for time in [
+            DateTime::new(2100, 1, 1, 0, 0, 0),
+            DateTime::new(2023, 2, 29, 0, 0, 0),
+            DateTime::new(2024, 0, 1, 0, 0, 0),
+            DateTime::new(2024, 1, 1, 24, 0, 0),
+        ] {
+            assert_eq!(rtc.set_time(&time), Err(RtcError::InvalidDateTime));
+        }
+        assert_eq!(rtc.release().writes().count(), 0);
+    }
+
+    #[test]
+    fn sets_the_alarm() {
+        let mut rtc = Pcf85063a::new(Bus::new(&[(CONTROL_2, RAISED)]));
+        let alarm = Alarm {
+            second: None,
+            minute: Some(30),
+            hour: Some(17),
+            day: None,
+            weekday: Some(Weekday::Monday),
+        };
+        rtc.set_alarm(&alarm).unwrap();
+
+        let bus = rtc.release();
+        assert_eq!(bus.reads(), &[CONTROL_2, CONTROL_2]);
+        let mut writes = bus.writes();
+        assert_eq!(
+            writes.next(),
+            Some(&[SECOND_ALARM, 0x80, 0x30, 0x17, 0x80, 1][..])
+        );
+        // clearing AF writes TF back as 1 to keep it
+        assert_eq!(writes.next(), Some(&[CONTROL_2, TIMER_FLAG | 0b101][..]));
+        assert_eq!(
+            writes.next(),
+            Some(&[CONTROL_2, ALARM_INTERRUPT | ALARM_FLAG | TIMER_FLAG | 0b101][..])
+        );
+        assert_eq!(writes.next(), None);
+        assert_eq!(
+            bus.registers[CONTROL_2 as usize],
+            ALARM_INTERRUPT | TIMER_FLAG | 0b101
+        );
+    }
+
+    #[test]
+    fn sets_the_timer() {
+        let mut rtc = Pcf85063a::new(Bus::new(&[(CONTROL_2, RAISED)]));
+        rtc.set_timer(90, TimerClock::Hz1).unwrap();
+
+        let bus = rtc.release();
+        let mut writes = bus.writes();
+        assert_eq!(writes.next(), Some(&[TIMER_MODE, 0][..]));
+        assert_eq!(writes.next(), Some(&[CONTROL_2, ALARM_FLAG | 0b101][..]));
+        assert_eq!(writes.next(), Some(&[TIMER_VALUE, 90, 0b1_0110][..]));
+        assert_eq!(writes.next(), None);
+        assert_eq!(bus.registers[CONTROL_2 as usize], ALARM_FLAG | 0b101);
+    }
+
+    #[test]
+    fn sets_the_clock_output_keeping_the_flags() {
+        let mut rtc = Pcf85063a::new(Bus::new(&[(CONTROL_2, RAISED)]));
+        rtc.set_clock_output(ClockOutput::Off).unwrap();
+
+        let bus = rtc.release();
+        let mut writes = bus.writes();
+        assert_eq!(
+            writes.next(),
+            Some(&[CONTROL_2, ALARM_FLAG | TIMER_FLAG | 0b111][..])
+        );
+        assert_eq!(writes.next(), None);
+        assert_eq!(bus.registers[CONTROL_2 as usize], RAISED | 0b111);
+    }
+
+    #[test]
+    fn acknowledge_clears_both_flags() {
+        let mut rtc = Pcf85063a::new(Bus::new(&[(CONTROL_2, RAISED)]));
+        assert_eq!(rtc.acknowledge(Inputs::from_register(0)), Ok(false));
+        assert_eq!(rtc.acknowledge(Inputs::from_register(1 << 5)), Ok(true));
+
+        let bus = rtc.release();
+        let mut writes = bus.writes();
+        assert_eq!(writes.next(), Some(&[CONTROL_2, 0b101][..]));
+        assert_eq!(writes.next(), None);
+    }
+
+    #[test]
+    fn weekdays() {
+        assert_eq!(
+            DateTime::new(2000, 1, 1, 0, 0, 0).weekday(),
+            Weekday::Saturday
+        );
+        assert_eq!(
+            DateTime::new(2024, 2, 29, 0, 0, 0).weekday(),
+            Weekday::Thursday
+        );
+        assert_eq!(
+            DateTime::new(2024, 3, 1, 0, 0, 0).weekday(),
+            Weekday::Friday
+        );
+        assert_eq!(
+            DateTime::new(2099, 12, 31, 0, 0, 0).weekday(),
+            Weekday::Thursday
+        );
+    }
+
+    #[test]
+    fn weekday_of_invalid_dates_doesnt_panic() {
+        for (year, month) in [(0, 0), (0, 1), (2024, 0), (2024, 13), (u16::MAX, 255)] {
+            DateTime::new(year, month, 255, 0, 0, 0).weekday();
+        }
+    }
+}
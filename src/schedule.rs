@@ -0,0 +1,373 @@
+//! Working out when to wake up next and going to sleep until then
+//!
+//! ```ignore
+//! use inky_frame_rs::schedule::{sleep_until_next, Schedule, TimeOfDay, WeekdaySet};
+//!
+//! const TIMES: [TimeOfDay; 3] = [
+//!     TimeOfDay::new(7, 0),
+//!     TimeOfDay::new(12, 0),
+//!     TimeOfDay::new(18, 0),
+//! ];
+//! const SCHEDULE: Schedule = Schedule::Daily {
+//!     times: &TIMES,
+//!     days: WeekdaySet::WEEKDAYS,
+//! };
+//!
+//! // after updating the display
+//! sleep_until_next(&SCHEDULE, &mut rtc, &mut power, &mut shift_register)?;
+//! ```
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+use embedded_hal::digital::v2::OutputPin;
+
+use crate::display::TryIsBusy;
+use crate::power::{PowerControl, ShutdownError};
+use crate::rtc::{days_in_month, Alarm, DateTime, Pcf85063a, RtcError, Weekday};
+
+const MINUTES_PER_DAY: u16 = 24 * 60;
+
+/// A set of days of the week
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct WeekdaySet(u8);
+
+impl WeekdaySet {
+    /// Monday to Friday
+    pub const WEEKDAYS: WeekdaySet = WeekdaySet(0b011_1110);
+    /// Saturday and Sunday
+    pub const WEEKEND: WeekdaySet = WeekdaySet(0b100_0001);
+    pub const EVERY_DAY: WeekdaySet = WeekdaySet(0b111_1111);
+
+    pub const fn empty() -> Self {
+        WeekdaySet(0)
+    }
+
+    pub const fn with(self, day: Weekday) -> Self {
+        WeekdaySet(self.0 | 1 << day.index())
+    }
+
+    pub const fn without(self, day: Weekday) -> Self {
+        WeekdaySet(self.0 & !(1 << day.index()))
+    }
+
+    pub const fn contains(self, day: Weekday) -> bool {
+        self.0 & (1 << day.index()) != 0
+    }
+}
+
+/// A time of day in 24 hour format
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct TimeOfDay {
+    pub hour: u8,
+    pub minute: u8,
+}
+
+impl TimeOfDay {
+    pub const fn new(hour: u8, minute: u8) -> Self {
+        TimeOfDay { hour, minute }
+    }
+
+    const fn minutes(self) -> u16 {
+        self.hour as u16 * 60 + self.minute as u16
+    }
+}
+
+/// When the frame should wake up
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Schedule<'a> {
+    /// At each of the times, on the given days
+    Daily {
+        times: &'a [TimeOfDay],
+        days: WeekdaySet,
+    },
+    /// Every `minutes` minutes counted from midnight, so 15 wakes on the hour
+    /// and at quarter past, half past and quarter to, up to a day
+    Every { minutes: u16 },
+}
+
+impl Schedule<'_> {
+    /// First wake after `now`, `None` if the schedule never wakes
+    ///
+    /// Past the end of 2099 the date wraps around to 2000, like the RTC's.
+    pub fn next_wake(&self, now: &DateTime) -> Option<DateTime> {
+        let minute_now = TimeOfDay::new(now.hour, now.minute).minutes();
+        match *self {
+            Schedule::Daily { times, days } => {
+                let mut date = *now;
+                // a week and a day, for a single day that is today but earlier
+                for day in 0..=7 {
+                    if days.contains(date.weekday()) {
+                        let next = times
+                            .iter()
+                            .filter(|time| time.hour < 24 && time.minute < 60)
+                            .map(|time| time.minutes())
+                            .filter(|&minutes| day > 0 || minutes > minute_now)
+                            .min();
+                        if let Some(minutes) = next {
+                            return Some(at_minute(&date, minutes));
+                        }
+                    }
+                    date = next_day(&date);
+                }
+                None
+            }
+            Schedule::Every { minutes } => {
+                if minutes == 0 || minutes > MINUTES_PER_DAY {
+                    return None;
+                }
+                let next = (minute_now / minutes + 1) * minutes;
+                if next < MINUTES_PER_DAY {
+                    Some(at_minute(now, next))
+                } else {
+                    Some(at_minute(&next_day(now), 0))
+                }
+            }
+        }
+    }
+}
+
+/// When going to sleep until the next wake fails
+#[derive(Debug, PartialEq, Eq)]
+pub enum SleepError<I2cE, GpioE> {
+    /// The schedule has no wake
+    NoWake,
+    /// Reading the time or arming the alarm failed
+    Rtc(RtcError<I2cE>),
+    /// Releasing the power hold failed
+    Shutdown(ShutdownError<GpioE>),
+}
+
+impl<I2cE: core::fmt::Debug, GpioE: core::fmt::Debug> core::fmt::Display
+    for SleepError<I2cE, GpioE>
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            SleepError::NoWake => write!(f, "Schedule never wakes up"),
+            SleepError::Rtc(error) => write!(f, "{}", error),
+            SleepError::Shutdown(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+/// Arms the RTC alarm for the schedule's next wake after the RTC's time,
+/// returning when that is, or `None` without touching the alarm if the
+/// schedule never wakes
+pub fn arm<I2C, I2cE>(
+    schedule: &Schedule,
+    rtc: &mut Pcf85063a<I2C>,
+) -> Result<Option<DateTime>, RtcError<I2cE>>
+where
+    I2C: Write<Error = I2cE> + WriteRead<Error = I2cE>,
+{
+    let now = rtc.time()?;
+    let Some(next) = schedule.next_wake(&now) else {
+        return Ok(None);
+    };
+    rtc.disable_timer()?;
+    rtc.clear_timer_flag()?;
+    // the next wake is at most a week away, so the day of the month is enough
+    rtc.set_alarm(&Alarm::at(&next))?;
+    Ok(Some(next))
+}
+
+/// Arms the RTC for the schedule's next wake and powers the board off
+///
+/// On battery the board loses power before this returns. On USB power it
+/// returns the time the alarm was set for and the board keeps running, so
+/// the caller has to loop or halt. Fails without arming anything while the
+/// display is still refreshing or its busy signal can't be read.
+pub fn sleep_until_next<I2C, I2cE, GpioOutput, GpioE>(
+    schedule: &Schedule,
+    rtc: &mut Pcf85063a<I2C>,
+    power: &mut PowerControl<GpioOutput>,
+    busy_signal: &mut impl TryIsBusy,
+) -> Result<DateTime, SleepError<I2cE, GpioE>>
+where
+    I2C: Write<Error = I2cE> + WriteRead<Error = I2cE>,
+    GpioOutput: OutputPin<Error = GpioE>,
+{
+    if busy_signal.try_is_busy().unwrap_or(true) {
+        return Err(SleepError::Shutdown(ShutdownError::DisplayBusy));
+    }
+    let next = arm(schedule, rtc)
+        .map_err(SleepError::Rtc)?
+        .ok_or(SleepError::NoWake)?;
+    power
+        .shutdown_until_wake(busy_signal)
+        .map_err(SleepError::Shutdown)?;
+    Ok(next)
+}
+
+/// `date` at `minutes` past midnight
+const fn at_minute(date: &DateTime, minutes: u16) -> DateTime {
+    DateTime::new(
+        date.year,
+        date.month,
+        date.day,
+        (minutes / 60) as u8,
+        (minutes % 60) as u8,
+        0,
+    )
+}
+
+/// The day after `date`, wrapping from 2099 back to 2000 like the RTC does
+const fn next_day(date: &DateTime) -> DateTime {
+    let mut next = *date;
+    if next.day < days_in_month(next.year, next.month) {
+        next.day += 1;
+    } else if next.month < 12 {
+        next.day = 1;
+        next.month += 1;
+    } else {
+        next.day = 1;
+        next.month = 1;
+        next.year = if next.year == 2099 {
+            2000
+        } else {
+            next.year + 1
+        };
+    }
+    next
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMES: [TimeOfDay; 2] = [TimeOfDay::new(18, 0), TimeOfDay::new(7, 0)];
+
+    fn date(year: u16, month: u8, day: u8) -> DateTime {
+        DateTime::new(year, month, day, 0, 0, 0)
+    }
+
+    #[test]
+    fn next_day_rolls_over() {
+        assert_eq!(next_day(&date(2024, 3, 14)), date(2024, 3, 15));
+        assert_eq!(next_day(&date(2024, 4, 30)), date(2024, 5, 1));
+        assert_eq!(next_day(&date(2024, 12, 31)), date(2025, 1, 1));
+        assert_eq!(next_day(&date(2023, 2, 28)), date(2023, 3, 1));
+        assert_eq!(next_day(&date(2024, 2, 28)), date(2024, 2, 29));
+        assert_eq!(next_day(&date(2024, 2, 29)), date(2024, 3, 1));
+        assert_eq!(next_day(&date(2099, 12, 31)), date(2000, 1, 1));
+    }
+
+    #[test]
+    fn daily_later_today() {
+        let schedule = Schedule::Daily {
+            times: &TIMES,
+            days: WeekdaySet::EVERY_DAY,
+        };
+        let now = DateTime::new(2024, 3, 14, 9, 30, 12);
+        assert_eq!(
+            schedule.next_wake(&now),
+            Some(DateTime::new(2024, 3, 14, 18, 0, 0))
+        );
+    }
+
+    #[test]
+    fn daily_exactly_at_a_scheduled_minute() {
+        let schedule = Schedule::Daily {
+            times: &TIMES,
+            days: WeekdaySet::EVERY_DAY,
+        };
+        for second in [0, 59] {
+            let now = DateTime::new(2024, 3, 14, 7, 0, second);
+            assert_eq!(
+                schedule.next_wake(&now),
+                Some(DateTime::new(2024, 3, 14, 18, 0, 0))
+            );
+        }
+        let now = DateTime::new(2024, 3, 14, 18, 0, 0);
+        assert_eq!(
+            schedule.next_wake(&now),
+            Some(DateTime::new(2024, 3, 15, 7, 0, 0))
+        );
+    }
+
+    #[test]
+    fn daily_over_month_and_year_ends() {
+        let schedule = Schedule::Daily {
+            times: &TIMES,
+            days: WeekdaySet::EVERY_DAY,
+        };
+        let now = DateTime::new(2024, 2, 29, 20, 0, 0);
+        assert_eq!(
+            schedule.next_wake(&now),
+            Some(DateTime::new(2024, 3, 1, 7, 0, 0))
+        );
+        let now = DateTime::new(2024, 12, 31, 23, 59, 59);
+        assert_eq!(
+            schedule.next_wake(&now),
+            Some(DateTime::new(2025, 1, 1, 7, 0, 0))
+        );
+    }
+
+    #[test]
+    fn weekdays_skip_the_weekend() {
+        let schedule = Schedule::Daily {
+            times: &TIMES,
+            days: WeekdaySet::WEEKDAYS,
+        };
+        // Friday evening, Saturday and Sunday all wake on Monday
+        for now in [
+            DateTime::new(2024, 3, 15, 19, 0, 0),
+            DateTime::new(2024, 3, 16, 6, 0, 0),
+            DateTime::new(2024, 3, 17, 12, 0, 0),
+        ] {
+            assert_eq!(
+                schedule.next_wake(&now),
+                Some(DateTime::new(2024, 3, 18, 7, 0, 0))
+            );
+        }
+    }
+
+    #[test]
+    fn single_day_a_week_later() {
+        let schedule = Schedule::Daily {
+            times: &TIMES,
+            days: WeekdaySet::empty().with(Weekday::Thursday),
+        };
+        let now = DateTime::new(2024, 3, 14, 19, 0, 0);
+        assert_eq!(
+            schedule.next_wake(&now),
+            Some(DateTime::new(2024, 3, 21, 7, 0, 0))
+        );
+    }
+
+    #[test]
+    fn every() {
+        let schedule = Schedule::Every { minutes: 15 };
+        let now = DateTime::new(2024, 3, 14, 10, 7, 0);
+        assert_eq!(
+            schedule.next_wake(&now),
+            Some(DateTime::new(2024, 3, 14, 10, 15, 0))
+        );
+        let now = DateTime::new(2024, 3, 14, 10, 15, 0);
+        assert_eq!(
+            schedule.next_wake(&now),
+            Some(DateTime::new(2024, 3, 14, 10, 30, 0))
+        );
+        let now = DateTime::new(2024, 4, 30, 23, 50, 0);
+        assert_eq!(
+            schedule.next_wake(&now),
+            Some(DateTime::new(2024, 5, 1, 0, 0, 0))
+        );
+    }
+
+    #[test]
+    fn never() {
+        let now = DateTime::new(2024, 3, 14, 10, 7, 0);
+        let schedule = Schedule::Daily {
+            times: &TIMES,
+            days: WeekdaySet::empty(),
+        };
+        assert_eq!(schedule.next_wake(&now), None);
+        let schedule = Schedule::Daily {
+            times: &[],
+            days: WeekdaySet::EVERY_DAY,
+        };
+        assert_eq!(schedule.next_wake(&now), None);
+        assert_eq!(Schedule::Every { minutes: 0 }.next_wake(&now), None);
+    }
+}
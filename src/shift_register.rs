@@ -1,62 +1,390 @@
-use crate::display::IsBusy;
+use crate::display::{IsBusy, TryIsBusy};
+use crate::piso::{NoDelay, ParallelInShiftRegister};
+use embedded_hal::blocking::delay::DelayUs;
 use embedded_hal::digital::v2::{InputPin, OutputPin};
-pub struct InkyFrameShiftRegister<GpioOutput, GpioInput> {
-    clock_pin: GpioOutput,
-    latch_pin: GpioOutput,
-    out_pin: GpioInput,
+
+/// The Inky Frame's 74HC165, decoding its buttons, wake flags and busy line
+pub struct InkyFrameShiftRegister<GpioOutput, GpioInput, D = NoDelay> {
+    register: ParallelInShiftRegister<GpioOutput, GpioInput, D>,
+    wake_inputs: Option<Inputs>,
 }
 
+const BUTTON_A_FLAG: u8 = 0;
+const BUTTON_B_FLAG: u8 = 1;
+const BUTTON_C_FLAG: u8 = 2;
+const BUTTON_D_FLAG: u8 = 3;
+const BUTTON_E_FLAG: u8 = 4;
+const RTC_ALARM_FLAG: u8 = 5;
+const EXTERNAL_TRIGGER_FLAG: u8 = 6;
 const IS_BUSY_FLAG: u8 = 7;
 
+/// The five buttons on the front of the Inky Frame
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Button {
+    A,
+    B,
+    C,
+    D,
+    E,
+}
+
+impl Button {
+    /// All buttons from left to right
+    pub const ALL: [Button; 5] = [Button::A, Button::B, Button::C, Button::D, Button::E];
+
+    /// Index of the button's bit in the shift register
+    pub const fn bit(self) -> u8 {
+        match self {
+            Button::A => BUTTON_A_FLAG,
+            Button::B => BUTTON_B_FLAG,
+            Button::C => BUTTON_C_FLAG,
+            Button::D => BUTTON_D_FLAG,
+            Button::E => BUTTON_E_FLAG,
+        }
+    }
+}
+
+/// Snapshot of every input on the shift register, taken with a single latch
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Inputs(u8);
+
+impl Inputs {
+    /// Decodes a value read from the shift register
+    pub const fn from_register(register: u8) -> Self {
+        Inputs(register)
+    }
+
+    /// The raw register value
+    pub const fn register(self) -> u8 {
+        self.0
+    }
+
+    /// Whether the button is held down
+    pub const fn is_pressed(self, button: Button) -> bool {
+        self.bit(button.bit())
+    }
+
+    /// Whether any of the buttons is held down
+    pub const fn any_pressed(self) -> bool {
+        self.0 & 0b1_1111 != 0
+    }
+
+    /// The buttons that are held down
+    pub fn pressed(self) -> impl Iterator<Item = Button> {
+        Button::ALL
+            .into_iter()
+            .filter(move |button| self.is_pressed(*button))
+    }
+
+    /// Whether the RTC's alarm or timer has gone off
+    pub const fn rtc_alarm(self) -> bool {
+        self.bit(RTC_ALARM_FLAG)
+    }
+
+    /// Whether the external trigger input is asserted
+    pub const fn external_trigger(self) -> bool {
+        self.bit(EXTERNAL_TRIGGER_FLAG)
+    }
+
+    /// Whether the e-ink display is busy, its busy line is low while it is
+    pub const fn display_busy(self) -> bool {
+        !self.bit(IS_BUSY_FLAG)
+    }
+
+    /// Why the board powered up, if these inputs were latched at boot
+    pub const fn wake_reason(self) -> WakeReason {
+        if self.rtc_alarm() {
+            WakeReason::RtcAlarm
+        } else if self.external_trigger() {
+            WakeReason::ExternalTrigger
+        } else if self.bit(BUTTON_A_FLAG) {
+            WakeReason::Button(Button::A)
+        } else if self.bit(BUTTON_B_FLAG) {
+            WakeReason::Button(Button::B)
+        } else if self.bit(BUTTON_C_FLAG) {
+            WakeReason::Button(Button::C)
+        } else if self.bit(BUTTON_D_FLAG) {
+            WakeReason::Button(Button::D)
+        } else if self.bit(BUTTON_E_FLAG) {
+            WakeReason::Button(Button::E)
+        } else {
+            WakeReason::UsbPower
+        }
+    }
+
+    const fn bit(self, index: u8) -> bool {
+        self.0 & (1 << index) != 0
+    }
+}
+
+/// What powered the Inky Frame up
+///
+/// When several inputs are latched the RTC alarm wins over the external
+/// trigger, which wins over the buttons from A to E.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WakeReason {
+    Button(Button),
+    RtcAlarm,
+    ExternalTrigger,
+    /// Nothing was latched, the board is running from USB or was reset
+    UsbPower,
+}
+
+impl<GpioOutput, GpioInput> InkyFrameShiftRegister<GpioOutput, GpioInput> {
+    /// Takes the pins without reading anything, see [`wake_reason`](Self::wake_reason)
+    pub fn new(clock_pin: GpioOutput, latch_pin: GpioOutput, out_pin: GpioInput) -> Self {
+        Self::from_shift_register(ParallelInShiftRegister::new(clock_pin, latch_pin, out_pin))
+    }
+}
+
 impl<GpioOutput, GpioInput, GpioE> InkyFrameShiftRegister<GpioOutput, GpioInput>
 where
     GpioOutput: OutputPin<Error = GpioE>,
     GpioInput: InputPin<Error = GpioE>,
 {
-    pub fn new(clock_pin: GpioOutput, latch_pin: GpioOutput, out_pin: GpioInput) -> Self {
+    /// Takes the pins and captures the wake state straight away
+    pub fn new_capturing(
+        clock_pin: GpioOutput,
+        latch_pin: GpioOutput,
+        out_pin: GpioInput,
+    ) -> Result<Self, GpioE> {
+        let mut shift_register = Self::new(clock_pin, latch_pin, out_pin);
+        shift_register.capture_wake_inputs()?;
+        Ok(shift_register)
+    }
+}
+
+impl<GpioOutput, GpioInput, D> InkyFrameShiftRegister<GpioOutput, GpioInput, D> {
+    /// Decodes an already configured register, e.g. one with edge delays
+    pub fn from_shift_register(
+        register: ParallelInShiftRegister<GpioOutput, GpioInput, D>,
+    ) -> Self {
         InkyFrameShiftRegister {
-            clock_pin,
-            latch_pin,
-            out_pin,
+            register,
+            wake_inputs: None,
         }
     }
 
-    pub fn read_register(&mut self) -> Result<u8, GpioE> {
-        self.latch_pin.set_low()?;
-        self.latch_pin.set_high()?;
-        let mut result = 0u8;
-        let mut bits = 8u8;
-
-        while bits > 0 {
-            bits -= 1;
-            result <<= 1;
-            if self.out_pin.is_high()? {
-                result |= 1;
-            } else {
-                result |= 0;
-            }
-            self.clock_pin.set_low()?;
-            self.clock_pin.set_high()?;
-        }
+    pub fn release(self) -> ParallelInShiftRegister<GpioOutput, GpioInput, D> {
+        self.register
+    }
+}
 
-        Ok(result)
+impl<GpioOutput, GpioInput, D, GpioE> InkyFrameShiftRegister<GpioOutput, GpioInput, D>
+where
+    GpioOutput: OutputPin<Error = GpioE>,
+    GpioInput: InputPin<Error = GpioE>,
+    D: DelayUs<u32>,
+{
+    pub fn read_register(&mut self) -> Result<u8, GpioE> {
+        self.register.read_byte()
     }
 
     pub fn read_register_bit(&mut self, bit_index: u8) -> Result<u8, GpioE> {
         Ok(self.read_register()? & (1u8 << bit_index))
     }
+
+    /// Reads all inputs at once
+    pub fn read_inputs(&mut self) -> Result<Inputs, GpioE> {
+        Ok(Inputs::from_register(self.read_register()?))
+    }
+
+    /// Whether the button is held down right now
+    pub fn is_pressed(&mut self, button: Button) -> Result<bool, GpioE> {
+        Ok(self.read_inputs()?.is_pressed(button))
+    }
+
+    /// Why the board powered up
+    ///
+    /// The wake state is captured once, by [`new_capturing`](InkyFrameShiftRegister::new_capturing),
+    /// [`capture_wake_inputs`](Self::capture_wake_inputs) or the first call
+    /// to this or [`wake_inputs`](Self::wake_inputs), and kept from then on.
+    /// Other reads don't capture it, so after [`new`](InkyFrameShiftRegister::new)
+    /// call this early at boot, before holding system power or driving the
+    /// display, or the latched inputs may be gone.
+    pub fn wake_reason(&mut self) -> Result<WakeReason, GpioE> {
+        Ok(self.wake_inputs()?.wake_reason())
+    }
+
+    /// The inputs latched at boot, see [`wake_reason`](Self::wake_reason)
+    pub fn wake_inputs(&mut self) -> Result<Inputs, GpioE> {
+        match self.wake_inputs {
+            Some(inputs) => Ok(inputs),
+            None => self.capture_wake_inputs(),
+        }
+    }
+
+    /// Reads the inputs now and keeps them as the wake state, unless it was
+    /// already captured
+    pub fn capture_wake_inputs(&mut self) -> Result<Inputs, GpioE> {
+        if let Some(inputs) = self.wake_inputs {
+            return Ok(inputs);
+        }
+        let inputs = self.read_inputs()?;
+        self.wake_inputs = Some(inputs);
+        Ok(inputs)
+    }
 }
 
-impl<GpioOutput, GpioInput, GpioE> IsBusy for InkyFrameShiftRegister<GpioOutput, GpioInput>
+impl<GpioOutput, GpioInput, D, GpioE> IsBusy for InkyFrameShiftRegister<GpioOutput, GpioInput, D>
 where
     GpioOutput: OutputPin<Error = GpioE>,
     GpioInput: InputPin<Error = GpioE>,
+    D: DelayUs<u32>,
 {
     fn is_busy(&mut self) -> bool {
-        if let Ok(res) = self.read_register_bit(IS_BUSY_FLAG) {
-            return res == 0;
+        if let Ok(inputs) = self.read_inputs() {
+            inputs.display_busy()
         } else {
-            return false;
+            false
         }
     }
 }
+
+impl<GpioOutput, GpioInput, D, GpioE> TryIsBusy for InkyFrameShiftRegister<GpioOutput, GpioInput, D>
+where
+    GpioOutput: OutputPin<Error = GpioE>,
+    GpioInput: InputPin<Error = GpioE>,
+    D: DelayUs<u32>,
+{
+    type Error = GpioE;
+
+    fn try_is_busy(&mut self) -> Result<bool, GpioE> {
+        Ok(self.read_inputs()?.display_busy())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::Cell;
+    use core::convert::Infallible;
+
+    /// A 74HC165 with its parallel inputs set to `inputs`
+    #[derive(Default)]
+    struct Chip {
+        inputs: Cell<u8>,
+        shift: Cell<u8>,
+        clock: Cell<bool>,
+    }
+
+    enum Role {
+        Clock,
+        Latch,
+    }
+
+    struct Output<'a>(&'a Chip, Role);
+
+    impl OutputPin for Output<'_> {
+        type Error = Infallible;
+
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            match self.1 {
+                Role::Clock => self.0.clock.set(false),
+                Role::Latch => self.0.shift.set(self.0.inputs.get()),
+            }
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            if let Role::Clock = self.1 {
+                if !self.0.clock.get() {
+                    self.0.shift.set(self.0.shift.get() << 1);
+                }
+                self.0.clock.set(true);
+            }
+            Ok(())
+        }
+    }
+
+    struct Data<'a>(&'a Chip);
+
+    impl InputPin for Data<'_> {
+        type Error = Infallible;
+
+        fn is_high(&self) -> Result<bool, Infallible> {
+            Ok(self.0.shift.get() & 0x80 != 0)
+        }
+
+        fn is_low(&self) -> Result<bool, Infallible> {
+            self.is_high().map(|high| !high)
+        }
+    }
+
+    fn shift_register(chip: &Chip) -> InkyFrameShiftRegister<Output<'_>, Data<'_>> {
+        InkyFrameShiftRegister::new(
+            Output(chip, Role::Clock),
+            Output(chip, Role::Latch),
+            Data(chip),
+        )
+    }
+
+    #[test]
+    fn decodes_every_bit() {
+        let chip = Chip::default();
+        let mut register = shift_register(&chip);
+        for bit in 0..8 {
+            chip.inputs.set(1 << bit);
+            let inputs = register.read_inputs().unwrap();
+            assert_eq!(inputs.register(), 1 << bit);
+            for button in Button::ALL {
+                let pressed = button.bit() == bit;
+                assert_eq!(inputs.is_pressed(button), pressed, "{button:?} bit {bit}");
+                assert_eq!(register.is_pressed(button).unwrap(), pressed);
+            }
+            assert_eq!(inputs.any_pressed(), bit < 5);
+            assert_eq!(inputs.rtc_alarm(), bit == 5);
+            assert_eq!(inputs.external_trigger(), bit == 6);
+            // the busy line is low while the display is busy
+            assert_eq!(inputs.display_busy(), bit != 7);
+            assert_eq!(register.is_busy(), bit != 7);
+        }
+    }
+
+    #[test]
+    fn captures_the_wake_state_once() {
+        let chip = Chip::default();
+        chip.inputs.set(1 << Button::C.bit());
+        let mut register = InkyFrameShiftRegister::new_capturing(
+            Output(&chip, Role::Clock),
+            Output(&chip, Role::Latch),
+            Data(&chip),
+        )
+        .unwrap();
+        chip.inputs.set(1 << 5);
+        assert_eq!(register.wake_reason(), Ok(WakeReason::Button(Button::C)));
+        assert!(register.read_inputs().unwrap().rtc_alarm());
+        assert_eq!(register.wake_reason(), Ok(WakeReason::Button(Button::C)));
+    }
+
+    #[test]
+    fn other_reads_dont_capture() {
+        let chip = Chip::default();
+        let mut register = shift_register(&chip);
+        assert!(register.is_busy());
+        chip.inputs.set(1 << 6);
+        assert_eq!(register.wake_reason(), Ok(WakeReason::ExternalTrigger));
+        chip.inputs.set(0);
+        assert_eq!(register.wake_reason(), Ok(WakeReason::ExternalTrigger));
+    }
+
+    #[test]
+    fn reads_a_known_byte() {
+        let chip = Chip::default();
+        chip.inputs.set(0b1010_0101);
+        let inputs = shift_register(&chip).read_inputs().unwrap();
+        assert_eq!(inputs.register(), 0b1010_0101);
+        assert!(inputs.is_pressed(Button::A));
+        assert!(!inputs.is_pressed(Button::B));
+        assert!(inputs.is_pressed(Button::C));
+        assert!(!inputs.is_pressed(Button::D));
+        assert!(!inputs.is_pressed(Button::E));
+        assert!(inputs.rtc_alarm());
+        assert!(!inputs.external_trigger());
+        assert!(!inputs.display_busy());
+        assert_eq!(inputs.wake_reason(), WakeReason::RtcAlarm);
+    }
+}